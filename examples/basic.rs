@@ -3,9 +3,8 @@
 //!
 //! Run with: cargo run --example basic
 
-//use eframe::egui;
 use egui_map::map::Map;
-use egui_map::map::objects::{/*MapLabel,*/ MapPoint, MapSegment};
+use egui_map::map::objects::{MapLabel, MapPoint, MapSegment};
 
 fn main() -> eframe::Result<()> {
     // 1. Create the nodes, keyed by id.
@@ -34,11 +33,9 @@ fn main() -> eframe::Result<()> {
     let seg2 = MapSegment::new((1, 3), [0.0, 0.0], [50.0, -80.0]);
     map.add_lines(vec![seg1, seg2]);
 
-    // A free-floating label. Its position is in screen coordinates.
-    /*map.add_labels(vec![MapLabel {
-        text: "Example region".to_string(),
-        center: egui::pos2(100.0, 100.0),
-    }]);*/
+    // A free-floating label, anchored in map coordinates. By default it is
+    // shown while zoomed out, until connection lines become visible.
+    map.add_labels(vec![MapLabel::at("Example region", [50.0, -20.0])]);
 
     eframe::run_ui_native(
         "egui-map: basic",
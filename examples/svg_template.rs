@@ -6,7 +6,7 @@
 
 use eframe::egui::{self, Align2, Color32, Pos2, Stroke, Ui, Vec2};
 use egui_map::map::Map;
-use egui_map::map::objects::{MapPoint, MapSegment, NodeTemplate, VisibilitySetting};
+use egui_map::map::objects::{MapPoint, NodeTemplate, VisibilitySetting};
 use std::rc::Rc;
 use std::time::Instant;
 
@@ -96,19 +96,10 @@ fn main() -> eframe::Result<()> {
         }
         points.push(point);
     }
-    let mut vec_segmnents = Vec::new();
-    let ids = vec![[1, 2], [2, 3]];
-    let mut cont = 0;
-    for id in ids {
-        let point1 = points.get(id[0]).unwrap();
-        let point2 = points.get(id[1]).unwrap();
-        let line = MapSegment::new((cont, cont + 1), point1.coords, point2.coords);
-        vec_segmnents.push(line);
-        cont += 1;
-    }
     let mut map = Map::new();
     map.add_points(points);
-    map.add_lines(vec_segmnents);
+    // One line per connection pair, drawn between the nodes listing it.
+    map.build_lines_from_connections();
     map.set_node_template(Rc::new(SvgNodes));
     // Show node names on hover so selection_ui gets called.
     map.settings.node_text_visibility = VisibilitySetting::Hover;
@@ -6,16 +6,30 @@
 //! The [`map::Map`] widget displays a set of nodes connected by lines, with
 //! support for:
 //!
-//! - Panning (click and drag) and zooming (mouse wheel; hold `Ctrl` — or `Cmd`
-//!   on macOS — to zoom faster).
+//! - Panning (click and drag) and zooming around the pointer (mouse wheel;
+//!   hold `Ctrl` — or `Cmd` on macOS — to zoom faster).
+//! - Trackpad and touch gestures: pinch to zoom, two-finger pan, and a
+//!   scroll-pans policy (see [`map::objects::ScrollAction`]).
+//! - Animated camera transitions (see [`map::Map::fly_to`]).
 //! - Spatial indexing of nodes through a kd-tree, so only the nodes inside the
 //!   current viewport are painted each frame.
+//! - Named node layers that can be toggled and ordered independently (see
+//!   [`map::objects::NodeLayer`]).
+//! - Custom overlays interleaved with the built-in content (see
+//!   [`map::objects::MapLayer`]).
+//! - Shortest-path routing over node connections (see [`map::routing`]).
+//! - Consistency checks for the loaded data (see [`map::validation`]).
+//! - Fuzzy search over node names (see [`map::search`]).
 //! - Node names and free-floating text labels with configurable visibility
 //!   rules (see [`map::objects::VisibilitySetting`]).
 //! - Pulsing notifications and blinking markers attached to nodes.
-//! - Custom node rendering and right-click context menus through the
+//! - Custom node rendering and right-click (or long-press) context menus through the
 //!   [`map::objects::NodeTemplate`] and [`map::objects::ContextMenuManager`]
 //!   traits.
+//! - Typed interaction events (node, line and background clicks, node hover)
+//!   returned by [`map::Map::show`] as a [`map::objects::MapResponse`].
+//! - Persistent node selection with multi-select and box/lasso selection
+//!   (see [`map::Map::selected_nodes`]).
 //! - Independent light and dark themes (see [`map::objects::MapSettings`]).
 //!
 //! ## Quick start
@@ -15,8 +15,12 @@
 //! - **Screen coordinates**: positions inside the widget's rectangle on screen.
 //!
 //! Both are related by the current zoom factor and viewport origin:
-//! `screen = map * zoom - origin`. Use [`Map::set_zoom`], [`Map::set_pos`] and
-//! [`Map::set_pos_from_nodeid`] to control the visible region.
+//! `screen = map * zoom - origin`. [`Map::screen_to_map`],
+//! [`Map::map_to_screen`] and [`Map::visible_map_rect`] apply it for the view
+//! of the last frame. Use [`Map::set_zoom`], [`Map::set_pos`] and
+//! [`Map::set_pos_from_nodeid`] to control the visible region, or
+//! [`Map::fit_bounds`], [`Map::fit_nodes`] and [`Map::fit_all`] to frame some
+//! content.
 //!
 //! ## Connecting nodes with lines
 //!
@@ -67,23 +71,72 @@
 //! animations and markers. Note that this replaces
 //! *all* built-in node rendering, including the node name labels: draw them
 //! yourself in [`NodeTemplate::node_ui`] if you need them.
-
-use crate::map::animation::Animation;
+//!
+//! ## Custom overlays
+//!
+//! Heatmaps, range rings and similar overlays implement
+//! [`objects::MapLayer`] and are registered with [`Map::add_map_layer`] at a
+//! z-index relative to the built-in content ([`Map::LINES_Z`],
+//! [`Map::NODES_Z`], ...).
+
+use crate::map::animation::{Animation, Easing, Flight};
+use crate::map::declutter::{
+    LabelKey, LabelPlacement, LabelRequest, NODE_NAME_ANCHORS, node_name_position, rotated_bounds,
+};
+use crate::map::groups::{GroupShape, GroupView, build as build_group_view, collapsed_groups};
 use crate::map::objects::{
-    ContextMenuManager, MapBounds, MapLabel, MapPoint, MapSegment, MapSettings, MapStyle, RawLine,
-    RawPoint, TextSettings, VisibilitySetting,
+    ClusterTemplate, ContextMenuManager, DragBehavior, MapBounds, MapEvent, MapLabel, MapLayer,
+    MapPoint, MapResponse, MapSegment, MapSettings, MapStyle, MapTransform, NodeCluster, NodeGroup,
+    NodeLayer, RawLine, RawPoint, RouteStyle, ScrollAction, SearchBox, SelectionMode,
+    SelectionShape, TextSettings, VisibilitySetting,
 };
+use crate::map::routing::{ConnectionIndex, Route, RouteMetric, RoutingGraph};
+use crate::map::search::{SearchIndex, SearchMatch};
+use crate::map::validation::{ValidationIssue, ValidationReport};
 use egui::{epaint::CircleShape, widgets::*, *};
 use kdtree::KdTree;
 use kdtree::distance::squared_euclidean;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::rc::Rc;
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use self::objects::NodeTemplate;
 
 pub mod animation;
+mod declutter;
+mod groups;
 pub mod objects;
+pub mod routing;
+pub mod search;
+pub mod validation;
+
+/// A route registered with [`Map::set_route`].
+#[derive(Clone)]
+struct MapRoute {
+    nodes: Vec<usize>,
+    style: RouteStyle,
+}
+
+/// A custom layer registered with [`Map::add_map_layer`].
+#[derive(Clone)]
+struct MapLayerEntry {
+    id: usize,
+    z_index: i32,
+    layer: Rc<dyn MapLayer>,
+}
+
+/// A raster image registered with [`Map::add_image_overlay`].
+#[derive(Clone)]
+struct ImageOverlay {
+    id: usize,
+    source: ImageSource<'static>,
+    /// Covered area, in map coordinates.
+    rect: Rect,
+    opacity: f32,
+    /// Set once loading the image failed and the error was logged.
+    load_failed: std::cell::Cell<bool>,
+}
 
 /// An interactive 2D map widget.
 ///
@@ -98,6 +151,9 @@ pub mod objects;
 /// Behavior and appearance are configured through the public
 /// [`settings`](Map::settings) field (see [`objects::MapSettings`]).
 ///
+/// Add it with `ui.add(&mut map)`, or call [`Map::show`] to also receive the
+/// clicks and hovers detected on nodes and lines as [`objects::MapEvent`]s.
+///
 /// Rendering of nodes and their visual effects (selection highlight,
 /// notifications and markers) can be fully customized by installing a
 /// [`objects::NodeTemplate`] implementation with [`Map::set_node_template`];
@@ -128,6 +184,9 @@ pub struct Map {
     previous_zoom: f32,
     points: Option<HashMap<usize, MapPoint>>,
     segments: Option<rstar::RTree<MapSegment>>,
+    /// The segments in `segments` by id, so a single line can be removed
+    /// from the R-tree without scanning it.
+    segments_by_id: HashMap<(usize, usize), MapSegment>,
     labels: Vec<MapLabel>,
     tree: Option<KdTree<f32, usize, [f32; 2]>>,
     visible_points: Vec<isize>,
@@ -143,7 +202,31 @@ pub struct Map {
     pub settings: MapSettings,
     menu_manager: Option<Rc<dyn ContextMenuManager>>,
     node_template: Option<Rc<dyn NodeTemplate>>,
+    cluster_template: Option<Rc<dyn ClusterTemplate>>,
+    clusters: Vec<NodeCluster>,
+    /// Nodes drawn as part of a cluster or a collapsed group this frame.
+    aggregated: HashSet<usize>,
+    node_groups: HashMap<usize, NodeGroup>,
+    group_view: Option<GroupView>,
+    node_layers: HashMap<String, NodeLayer>,
+    map_layers: Vec<MapLayerEntry>,
+    image_overlays: Vec<ImageOverlay>,
+    next_image_overlay_id: usize,
+    next_map_layer_id: usize,
+    routes: BTreeMap<usize, MapRoute>,
+    hop_highlight: Option<(HashMap<usize, usize>, usize)>,
+    point_load_issues: Vec<ValidationIssue>,
+    line_load_issues: Vec<ValidationIssue>,
+    derived_lines: Option<ConnectionIndex>,
+    search_index: SearchIndex,
+    search_query: String,
+    search_results: Vec<SearchMatch>,
     markers: HashMap<usize, usize>,
+    hovered_node: Option<usize>,
+    selection: HashSet<usize>,
+    selection_gesture: Option<Vec<RawPoint>>,
+    flight: Option<Flight>,
+    pending_fit: Option<(RawPoint, RawPoint, f32)>,
 }
 
 impl Default for Map {
@@ -156,8 +239,42 @@ impl Default for Map {
 impl Widget for &mut Map {
     /// Renders the map, handling panning (drag), zooming (mouse wheel) and the
     /// right-click context menu if one was installed.
+    ///
+    /// Use [`Map::show`] instead to also get the interaction events of the
+    /// frame.
     fn ui(self, ui: &mut egui::Ui) -> Response {
+        self.show(ui).response
+    }
+}
+
+impl Map {
+    /// Space left around the members of a clicked cluster, in screen points.
+    const CLUSTER_FIT_PADDING: f32 = 40.0;
+
+    /// Number of matches listed by the built-in search box.
+    const SEARCH_BOX_RESULTS: usize = 8;
+
+    /// Z-index of the free-floating text labels, for [`Map::add_map_layer`].
+    pub const LABELS_Z: i32 = 0;
+    /// Z-index of the connection lines, for [`Map::add_map_layer`].
+    pub const LINES_Z: i32 = 100;
+    /// Z-index of the nodes and their effects, for [`Map::add_map_layer`].
+    pub const NODES_Z: i32 = 200;
+    /// Z-index of the node markers, for [`Map::add_map_layer`].
+    pub const MARKERS_Z: i32 = 300;
+
+    /// Renders the map and returns the interactions detected during this
+    /// frame.
+    ///
+    /// Behaves exactly like adding the widget with `ui.add(&mut map)`, but
+    /// also reports which nodes and lines were clicked or hovered (see
+    /// [`MapEvent`]), so callers don't need to repeat the hit-testing
+    /// themselves.
+    pub fn show(&mut self, ui: &mut Ui) -> MapResponse {
         let rect = self.calculate_widget_dimensions(ui);
+        if let Some((min, max, padding)) = self.pending_fit.take() {
+            self.fit_rect(min, max, padding);
+        }
 
         // we define the initial coordinate as the center of such rectangle
         self.reference.dist = rect.distance();
@@ -168,39 +285,51 @@ impl Widget for &mut Map {
 
         let inner_response = canvas.show(ui, |ui| {
             let _span = tracing::info_span!("paint_map").entered();
+            let mut events = Vec::new();
 
             if ui.is_rect_visible(self.map_area) {
                 let (resp, paint) =
                     ui.allocate_painter(self.map_area.size(), egui::Sense::click_and_drag());
-                let vec = resp.drag_delta();
-                if vec.length() != 0.0 {
-                    let _span = tracing::info_span!("calculating_points_in_visible_area").entered();
-
-                    let coords = RawPoint::from(vec.to_pos2());
-                    let new_pos = self.reference.pos - (coords / self.zoom);
-                    self.set_pos(new_pos.into());
-                }
-                if self.zoom < self.settings.line_visible_zoom {
-                    // filling text settings
-                    let mut text_settings = TextSettings {
-                        size: 12.00 * self.zoom * 2.00,
-                        anchor: Align2::CENTER_CENTER,
-                        family: FontFamily::Proportional,
-                        text: String::new(),
-                        position: RawPoint::default(),
-                        text_color: ui.visuals().text_color(),
-                    };
-                    for label in &self.labels {
-                        text_settings.text.clone_from(&label.text);
-                        text_settings.position = RawPoint::from(label.center);
-                        self.paint_label(&paint, &text_settings);
+                if self.flight.is_some() {
+                    if self.camera_input(ui, &resp) {
+                        self.flight = None;
+                    } else if self.advance_flight(Instant::now()) {
+                        ui.ctx().request_repaint();
                     }
                 }
+                events = self.capture_drag_events(ui, &resp);
+                self.update_group_view();
+                self.update_clusters();
+
+                // custom layers are interleaved with the built-in content by z-index
+                let transform = self.map_transform();
+                let visible_area = self.visible_map_rect();
+                let map_layers = self.map_layers.clone();
+                let mut pending_layers = map_layers.iter().peekable();
+                let mut paint_layers_below = |z_index: Option<i32>| {
+                    while let Some(entry) = pending_layers
+                        .next_if(|entry| z_index.is_none_or(|z_index| entry.z_index < z_index))
+                    {
+                        entry.layer.paint(&paint, &transform, visible_area);
+                    }
+                };
+
+                let name_hover = self.name_hover_node(&resp);
+                let placement = self.settings.declutter_labels.then(|| {
+                    self.place_labels(&paint, &transform, name_hover, ui.visuals().text_color())
+                });
+
+                self.paint_image_overlays(&paint, &transform);
+                paint_layers_below(Some(Self::LABELS_Z));
+                self.paint_map_labels(
+                    &paint,
+                    &transform,
+                    ui.visuals().text_color(),
+                    placement.as_ref(),
+                );
 
-                let rect_midpoint = RawPoint::from(self.map_area.center());
-                let min_point = self.current.pos - rect_midpoint;
+                let min_point = self.viewport_origin();
                 let vec_points = &self.visible_points;
-                let hashm = &self.points;
 
                 // Safety net: drop stale notifications even if their node is
                 // outside the viewport and never finishes its animation.
@@ -208,20 +337,37 @@ impl Widget for &mut Map {
                 self.entities
                     .retain(|_, init| now.duration_since(*init).as_secs_f32() < 10.0);
 
+                paint_layers_below(Some(Self::LINES_Z));
                 self.paint_map_lines(&paint, &min_point);
-
-                if let Ok(nodes_to_remove) =
-                    self.paint_map_points(vec_points, hashm, &paint, ui, &min_point, &resp)
-                {
+                self.paint_group_edges(&paint, &transform);
+                self.paint_routes(&paint, &transform, visible_area);
+
+                paint_layers_below(Some(Self::NODES_Z));
+                self.paint_group_shapes(&paint, ui, &transform);
+
+                if let Ok(nodes_to_remove) = self.paint_map_points(
+                    vec_points,
+                    &paint,
+                    ui,
+                    &min_point,
+                    name_hover,
+                    placement.as_ref(),
+                ) {
                     for node in nodes_to_remove {
                         self.entities.remove(&node);
                     }
                 }
 
+                self.paint_clusters(&paint, ui, &transform);
+
+                paint_layers_below(Some(Self::MARKERS_Z));
                 for marker in &self.markers {
-                    if let Some(point) = self.points.as_ref().unwrap().get(marker.1) {
+                    if let Some(point) = self.points.as_ref().unwrap().get(marker.1)
+                        && self.is_node_shown(point)
+                        && !self.aggregated.contains(marker.1)
+                    {
                         let adjusted_point = RawPoint::from(point.coords) * self.zoom - min_point;
-                        if let Some(template) = &self.node_template {
+                        if let Some(template) = self.template_for(point) {
                             template.marker_ui(ui, adjusted_point.into(), self.zoom);
                         } else {
                             let mut shapes = Vec::new();
@@ -255,9 +401,15 @@ impl Widget for &mut Map {
                     }
                 }
 
+                self.paint_waypoints(&paint, &transform, visible_area);
+
+                paint_layers_below(None);
+
+                self.paint_selection_gesture(&paint, &min_point);
+
                 self.paint_sub_components(ui, self.map_area);
 
-                self.capture_mouse_events(ui, &resp);
+                events.extend(self.capture_mouse_events(ui, &resp));
 
                 if self.zoom != self.previous_zoom {
                     let _span = tracing::info_span!("calculating viewport with zoom").entered();
@@ -266,22 +418,39 @@ impl Widget for &mut Map {
                     self.previous_zoom = self.zoom;
                 }
 
-                if let Some(menu_mon) = &mut self.menu_manager {
-                    resp.context_menu(|ui| {
-                        menu_mon.ui(ui);
-                    });
+                if let Some(menu_mon) = &self.menu_manager {
+                    let open = if resp.long_touched() {
+                        self.settings.long_press_menu
+                    } else {
+                        resp.secondary_clicked()
+                    };
+                    let command = if open {
+                        Some(SetOpenCommand::Bool(true))
+                    } else if resp.clicked() {
+                        Some(SetOpenCommand::Bool(false))
+                    } else {
+                        None
+                    };
+                    Popup::menu(&resp)
+                        .open_memory(command)
+                        .at_pointer_fixed()
+                        .show(|ui| {
+                            menu_mon.ui(ui);
+                        });
                 }
 
                 #[cfg(feature = "debug_overlay")]
                 self.print_debug_info(paint, resp);
             }
+            events
         });
         ui.allocate_space(self.map_area.size());
-        inner_response.response
+        MapResponse {
+            response: inner_response.response,
+            events: inner_response.inner,
+        }
     }
-}
 
-impl Map {
     /// Creates an empty map widget with default [`MapSettings`].
     ///
     /// The widget displays nothing until nodes are loaded with
@@ -305,8 +474,32 @@ impl Map {
             entities: HashMap::new(),
             menu_manager: None,
             node_template: None,
+            cluster_template: None,
+            clusters: Vec::new(),
+            aggregated: HashSet::new(),
+            node_groups: HashMap::new(),
+            group_view: None,
+            node_layers: HashMap::new(),
+            map_layers: Vec::new(),
+            image_overlays: Vec::new(),
+            next_image_overlay_id: 0,
+            next_map_layer_id: 0,
+            routes: BTreeMap::new(),
+            hop_highlight: None,
+            point_load_issues: Vec::new(),
+            line_load_issues: Vec::new(),
+            derived_lines: None,
+            search_index: SearchIndex::default(),
+            search_query: String::new(),
+            search_results: Vec::new(),
             markers: HashMap::new(),
             segments: None,
+            segments_by_id: HashMap::new(),
+            hovered_node: None,
+            selection: HashSet::new(),
+            selection_gesture: None,
+            flight: None,
+            pending_fit: None,
         }
     }
 
@@ -358,7 +551,10 @@ impl Map {
     ///
     /// The kd-tree built here is what enables viewport culling and
     /// nearest-neighbor hover lookups, so calling this method on every frame is
-    /// discouraged; call it only when the node set changes.
+    /// discouraged; call it only when the node set is replaced, and use
+    /// [`Map::insert_point`], [`Map::move_point`] and [`Map::remove_point`]
+    /// for small changes. Selected nodes missing from the new set are
+    /// deselected.
     ///
     /// # Examples
     ///
@@ -381,6 +577,7 @@ impl Map {
         let mut hash_map = HashMap::new();
         let mut min = RawPoint::new(f32::INFINITY, f32::INFINITY);
         let mut max = RawPoint::new(f32::NEG_INFINITY, f32::NEG_INFINITY);
+        self.point_load_issues.clear();
         for entry in points {
             for i in 0..min.components.len() {
                 if entry.coords[i] < min.components[i] {
@@ -391,11 +588,17 @@ impl Map {
                 }
             }
             let _result = tree.add(entry.coords, entry.get_id());
-            hash_map.insert(entry.get_id(), entry);
+            if let Some(previous) = hash_map.insert(entry.get_id(), entry) {
+                self.point_load_issues
+                    .push(ValidationIssue::DuplicateNodeId { id: previous.id });
+            }
         }
         // We stablish the max and min coordinates in this map, this wont change until we change the point hash map
         self.reference.min = min;
         self.reference.max = max;
+        self.selection.retain(|id| hash_map.contains_key(id));
+        self.search_index = SearchIndex::new(hash_map.values());
+        self.group_view = None;
         self.points = Some(hash_map);
         self.tree = Some(tree);
         self.reference.pos = RawLine::new(min, max).midpoint();
@@ -413,6 +616,10 @@ impl Map {
         }
         self.current = self.reference.clone();
         self.calculate_visible_points();
+        if self.derived_lines.is_some() {
+            self.build_lines_from_connections();
+        }
+        self.refresh_search_results();
     }
 
     /// Loads the node set and (re)builds the spatial index.
@@ -424,7 +631,10 @@ impl Map {
     ///
     /// The kd-tree built here is what enables viewport culling and
     /// nearest-neighbor hover lookups, so calling this method on every frame is
-    /// discouraged; call it only when the node set changes.
+    /// discouraged; call it only when the node set is replaced, and use
+    /// [`Map::insert_point`], [`Map::move_point`] and [`Map::remove_point`]
+    /// for small changes. Selected nodes missing from the new set are
+    /// deselected.
     ///
     /// # Examples
     ///
@@ -449,8 +659,16 @@ impl Map {
         let mut min = RawPoint::new(f32::INFINITY, f32::INFINITY);
         let mut max = RawPoint::new(f32::NEG_INFINITY, f32::NEG_INFINITY);
         let mut tree = KdTree::<f32, usize, [f32; 2]>::new(2);
+        self.point_load_issues.clear();
 
         for entry in hash_map.iter() {
+            if *entry.0 != entry.1.id {
+                self.point_load_issues
+                    .push(ValidationIssue::MismatchedNodeKey {
+                        key: *entry.0,
+                        id: entry.1.id,
+                    });
+            }
             for i in 0..min.components.len() {
                 if entry.1.coords[i] < min.components[i] {
                     min.components[i] = entry.1.coords[i];
@@ -465,6 +683,9 @@ impl Map {
         // We stablish the max and min coordinates in this map, this wont change until we change the point hash map
         self.reference.min = min;
         self.reference.max = max;
+        self.selection.retain(|id| hash_map.contains_key(id));
+        self.search_index = SearchIndex::new(hash_map.values());
+        self.group_view = None;
         self.points = Some(hash_map);
         self.tree = Some(tree);
         self.reference.pos = RawLine::new(min, max).midpoint();
@@ -482,27 +703,303 @@ impl Map {
         }
         self.current = self.reference.clone();
         self.calculate_visible_points();
+        if self.derived_lines.is_some() {
+            self.build_lines_from_connections();
+        }
+        self.refresh_search_results();
+    }
+
+    /// Adds a single node, or replaces the node with the same id, and returns
+    /// the node it replaced.
+    ///
+    /// Unlike [`Map::add_points`], this updates the spatial index and the map
+    /// bounds in place and leaves the view where it is, so it is cheap enough
+    /// to call for a few nodes every frame. If no points were loaded yet, this
+    /// starts a new node set.
+    pub fn insert_point(&mut self, point: MapPoint) -> Option<MapPoint> {
+        let _span = tracing::info_span!("insert_point").entered();
+        if self.points.is_none() {
+            self.points = Some(HashMap::new());
+            self.tree = Some(KdTree::new(2));
+            self.reference.min = RawPoint::new(f32::INFINITY, f32::INFINITY);
+            self.reference.max = RawPoint::new(f32::NEG_INFINITY, f32::NEG_INFINITY);
+            if self.reference.dist == 0.0 {
+                self.reference.dist = 3000.00;
+            }
+        }
+        let id = point.get_id();
+        let coords = point.coords;
+        match &point.name {
+            Some(name) => self.search_index.insert(id, name),
+            None => self.search_index.remove(id),
+        }
+        let connections = point.connections.clone();
+        self.forget_point_load_issues(id);
+        self.invalidate_group_view(&point);
+        let previous = self.points.as_mut().unwrap().insert(id, point);
+        if let Some(old) = &previous {
+            self.invalidate_group_view(old);
+            self.unindex_point(id, old.coords);
+        }
+        self.index_point(id, coords);
+        if let Some(old) = &previous {
+            self.shrink_bounds(old.coords);
+        }
+        self.adjust_bounds();
+        self.calculate_visible_points();
+        let old_connections = previous.as_ref().map_or(&[][..], |old| &old.connections);
+        self.derive_node_lines(id, old_connections, &connections);
+        self.refresh_search_results();
+        previous
+    }
+
+    /// Replaces an already loaded node with `point` (matched by id) and
+    /// returns the previous version.
+    ///
+    /// Does nothing and returns `None` if no node with that id is loaded; use
+    /// [`Map::insert_point`] to add new nodes. The view is left untouched.
+    pub fn update_point(&mut self, point: MapPoint) -> Option<MapPoint> {
+        let _span = tracing::info_span!("update_point").entered();
+        let known = self
+            .points
+            .as_ref()
+            .is_some_and(|points| points.contains_key(&point.get_id()));
+        if known {
+            self.insert_point(point)
+        } else {
+            None
+        }
+    }
+
+    /// Moves the node with the given id to `coords`, updating the spatial
+    /// index and the map bounds in place.
+    ///
+    /// Returns `false` if no node with that id is loaded. The view is left
+    /// untouched.
+    pub fn move_point(&mut self, node_id: usize, coords: [f32; 2]) -> bool {
+        let _span = tracing::info_span!("move_point").entered();
+        let Some(point) = self
+            .points
+            .as_mut()
+            .and_then(|points| points.get_mut(&node_id))
+        else {
+            return false;
+        };
+        let old_coords = std::mem::replace(&mut point.coords, coords);
+        if old_coords != coords {
+            if self
+                .group_view
+                .as_ref()
+                .is_some_and(|view| view.is_affected_by(point))
+            {
+                self.group_view = None;
+            }
+            self.unindex_point(node_id, old_coords);
+            self.index_point(node_id, coords);
+            self.shrink_bounds(old_coords);
+            self.adjust_bounds();
+            self.calculate_visible_points();
+            self.move_derived_lines(node_id);
+        }
+        true
+    }
+
+    /// Removes the node with the given id and returns it.
+    ///
+    /// The node is also deselected and its pending notification dropped. The
+    /// view is left untouched. Lines attached to the node are only removed if
+    /// they were built with [`Map::build_lines_from_connections`].
+    pub fn remove_point(&mut self, node_id: usize) -> Option<MapPoint> {
+        let _span = tracing::info_span!("remove_point").entered();
+        let removed = self.points.as_mut()?.remove(&node_id)?;
+        self.forget_point_load_issues(node_id);
+        self.unindex_point(node_id, removed.coords);
+        self.shrink_bounds(removed.coords);
+        self.selection.remove(&node_id);
+        self.entities.remove(&node_id);
+        self.search_index.remove(node_id);
+        self.invalidate_group_view(&removed);
+        self.refresh_search_results();
+        self.adjust_bounds();
+        self.calculate_visible_points();
+        self.derive_node_lines(node_id, &removed.connections, &[]);
+        Some(removed)
+    }
+
+    /// Finds up to `limit` nodes whose name matches `query`, best first.
+    ///
+    /// Matching ignores case and accepts prefixes, words inside the name,
+    /// substrings, scattered letters and small typos, ranked in that order;
+    /// see [`SearchIndex::search`]. Unnamed nodes are never found. The index
+    /// is kept up to date as nodes are loaded, inserted or removed, so this
+    /// is cheap enough to call on every keystroke.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use egui_map::map::Map;
+    /// use egui_map::map::objects::MapPoint;
+    ///
+    /// let mut jita = MapPoint::new(1, [0.0, 0.0]);
+    /// jita.set_name("Jita".to_string());
+    /// let mut map = Map::new();
+    /// map.add_points(vec![jita]);
+    ///
+    /// let found = map.search("JIT", 10);
+    /// assert_eq!(found[0].id, 1);
+    /// map.set_pos_from_nodeid(found[0].id);
+    /// ```
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchMatch> {
+        let _span = tracing::info_span!("search").entered();
+        self.search_index.search(query, limit)
+    }
+
+    /// Recomputes the matches listed by the search box after the nodes
+    /// change.
+    fn refresh_search_results(&mut self) {
+        self.search_results = self.search(&self.search_query, Self::SEARCH_BOX_RESULTS);
+    }
+
+    /// Builds the routing graph of the loaded nodes and lines.
+    ///
+    /// Keep the graph around to compute several routes; it is not updated
+    /// when the nodes change.
+    pub fn routing_graph(&self) -> RoutingGraph {
+        match &self.points {
+            Some(points) => RoutingGraph::new(points, self.lines()),
+            None => RoutingGraph::default(),
+        }
+    }
+
+    /// Finds the shortest route between two nodes following their
+    /// connections. See [`RoutingGraph::shortest_path`].
+    ///
+    /// This builds the routing graph on every call; use
+    /// [`Map::routing_graph`] for repeated queries.
+    pub fn shortest_path(&self, from: usize, to: usize, metric: RouteMetric) -> Option<Route> {
+        self.routing_graph().shortest_path(from, to, metric)
+    }
+
+    /// Returns the nodes within `max_hops` jumps of `origin`, grouped by hop
+    /// count. See [`RoutingGraph::neighborhood`].
+    pub fn neighborhood(&self, origin: usize, max_hops: usize) -> Vec<Vec<usize>> {
+        self.routing_graph().neighborhood(origin, max_hops)
+    }
+
+    /// Tints the nodes within `max_hops` jumps of `origin` by their distance
+    /// to it, replacing any previous highlight.
+    ///
+    /// The origin is drawn in [`MapStyle::alert_color`] and farther nodes
+    /// fade towards [`MapStyle::fill_color`]. The distances are computed once
+    /// here, so call it again after changing the connections. Only the
+    /// built-in node shapes are tinted; templates can read the distances with
+    /// [`Map::highlighted_hops`].
+    pub fn set_distance_highlight(&mut self, origin: usize, max_hops: usize) {
+        let _span = tracing::info_span!("set_distance_highlight").entered();
+        let hops = self
+            .neighborhood(origin, max_hops)
+            .into_iter()
+            .enumerate()
+            .flat_map(|(hops, ring)| ring.into_iter().map(move |id| (id, hops)))
+            .collect();
+        self.hop_highlight = Some((hops, max_hops));
+    }
+
+    /// Removes the highlight set with [`Map::set_distance_highlight`].
+    pub fn clear_distance_highlight(&mut self) {
+        self.hop_highlight = None;
+    }
+
+    /// Returns how many jumps away from the highlight origin a node is, or
+    /// `None` if it is not highlighted.
+    pub fn highlighted_hops(&self, node_id: usize) -> Option<usize> {
+        self.hop_highlight.as_ref()?.0.get(&node_id).copied()
+    }
+
+    /// Returns the color of the built-in node shape, tinted by the distance
+    /// highlight.
+    fn node_fill_color(&self, node_id: usize) -> Color32 {
+        let style = self.current_style();
+        match &self.hop_highlight {
+            Some((hops, max_hops)) if hops.contains_key(&node_id) => {
+                let t = hops[&node_id] as f32 / (*max_hops + 1) as f32;
+                style.alert_color.lerp_to_gamma(style.fill_color, t)
+            }
+            _ => style.fill_color,
+        }
+    }
+
+    /// Returns the loaded node with the given id.
+    pub fn point(&self, node_id: usize) -> Option<&MapPoint> {
+        self.points.as_ref()?.get(&node_id)
+    }
+
+    /// Returns every loaded node, in no particular order.
+    pub fn points(&self) -> impl Iterator<Item = &MapPoint> {
+        self.points.iter().flat_map(|points| points.values())
+    }
+
+    /// Adds a node to the kd-tree and grows the map bounds to include it.
+    fn index_point(&mut self, node_id: usize, coords: [f32; 2]) {
+        if let Some(tree) = self.tree.as_mut() {
+            let _result = tree.add(coords, node_id);
+        }
+        for (i, value) in coords.into_iter().enumerate() {
+            self.reference.min.components[i] = self.reference.min.components[i].min(value);
+            self.reference.max.components[i] = self.reference.max.components[i].max(value);
+        }
+    }
+
+    fn unindex_point(&mut self, node_id: usize, coords: [f32; 2]) {
+        if let Some(tree) = self.tree.as_mut() {
+            let _result = tree.remove(&coords, &node_id);
+        }
+    }
+
+    /// Recomputes the map bounds after a node left `coords`, which is only
+    /// needed when it was lying on the border of the bounding box.
+    fn shrink_bounds(&mut self, coords: [f32; 2]) {
+        let on_border = coords.into_iter().enumerate().any(|(i, value)| {
+            value == self.reference.min.components[i] || value == self.reference.max.components[i]
+        });
+        if !on_border {
+            return;
+        }
+        let mut min = RawPoint::new(f32::INFINITY, f32::INFINITY);
+        let mut max = RawPoint::new(f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for point in self.points.iter().flat_map(|points| points.values()) {
+            for (i, value) in point.coords.into_iter().enumerate() {
+                min.components[i] = min.components[i].min(value);
+                max.components[i] = max.components[i].max(value);
+            }
+        }
+        self.reference.min = min;
+        self.reference.max = max;
     }
 
     /// Centers the view on the node with the given id.
     ///
     /// Does nothing if no points have been loaded yet or if `node_id` is
-    /// unknown.
+    /// unknown. Like [`Map::set_pos`], this jumps instantly and cancels any
+    /// transition started with [`Map::fly_to`].
     pub fn set_pos_from_nodeid(&mut self, node_id: usize) {
         let _span = tracing::info_span!("set_pos_from_nodeid").entered();
         if let Some(hash_map) = &self.points
             && let Some(map_point) = hash_map.get(&node_id)
         {
+            self.flight = None;
             self.reference.pos = RawPoint::from(map_point.coords);
             self.adjust_bounds();
             self.calculate_visible_points();
         }
     }
 
-    /// Centers the view on the given map coordinates.
+    /// Centers the view on the given map coordinates, cancelling any
+    /// transition started with [`Map::fly_to`].
     pub fn set_pos(&mut self, position: [f32; 2]) {
         let _span = tracing::info_span!("set_pos").entered();
         let point = RawPoint::from(position);
+        self.flight = None;
         self.reference.pos = point;
         self.adjust_bounds();
         self.calculate_visible_points();
@@ -516,7 +1013,9 @@ impl Map {
 
     /// Replaces the set of free-floating text labels drawn on the map.
     ///
-    /// Labels are only rendered while the zoom level is below
+    /// Each label is drawn at its map position while the zoom level is within
+    /// its [`min_zoom`](MapLabel::min_zoom) /
+    /// [`max_zoom`](MapLabel::max_zoom) range, which by default ends at
     /// [`MapSettings::line_visible_zoom`].
     pub fn add_labels(&mut self, labels: Vec<MapLabel>) {
         let _span = tracing::info_span!("add_labels").entered();
@@ -525,6 +1024,9 @@ impl Map {
 
     /// Replaces the set of connection lines between nodes.
     ///
+    /// This rebuilds the R-tree from scratch; use [`Map::insert_line`] and
+    /// [`Map::remove_line`] to change a few lines.
+    ///
     /// Lines are keyed by a connection id that the endpoint nodes must
     /// reference through [`MapPoint::connections`] — push each line's key into
     /// the `connections` of the nodes it joins. The segments are stored in an
@@ -539,8 +1041,10 @@ impl Map {
         // Intern the keys as Rc<str> and build the broad-phase spatial index
         // over the line bounding boxes, so viewport culling and hit-testing
         // discard whole regions without touching every segment.
+        self.line_load_issues = validation::check_segment_ids(&segments);
+        self.derived_lines = None;
 
-        self.segments = Some(rstar::RTree::bulk_load(segments));
+        self.load_segments(segments);
     }
 
     /// Replaces the set of connection lines between nodes, from a map keyed
@@ -553,8 +1057,242 @@ impl Map {
     /// them 1:1 by id, with no intermediate ordering to preserve).
     pub fn add_hashmap_lines(&mut self, segments: HashMap<(usize, usize), MapSegment>) {
         let _span = tracing::info_span!("add_hashmap_lines").entered();
+        self.line_load_issues = segments
+            .iter()
+            .filter(|(key, segment)| **key != segment.id)
+            .map(|(key, segment)| ValidationIssue::MismatchedSegmentKey {
+                key: *key,
+                id: segment.id,
+            })
+            .collect();
+        self.derived_lines = None;
         let segments: Vec<MapSegment> = segments.into_values().collect();
-        self.segments = Some(rstar::RTree::bulk_load(segments));
+        self.load_segments(segments);
+    }
+
+    /// Replaces the lines with one straight line per connection listed in
+    /// [`MapPoint::connections`], running between the nodes it joins.
+    ///
+    /// This saves building a [`MapSegment`] by hand for every connection.
+    /// Connections are paired with their nodes as described in
+    /// [`RoutingGraph::new`]. From then on the lines follow the nodes: they
+    /// are rebuilt by [`Map::add_points`] and updated by
+    /// [`Map::insert_point`], [`Map::move_point`] and [`Map::remove_point`],
+    /// until lines are loaded again with [`Map::add_lines`] or
+    /// [`Map::add_hashmap_lines`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use egui_map::map::Map;
+    /// use egui_map::map::objects::MapPoint;
+    ///
+    /// let mut a = MapPoint::new(1, [0.0, 0.0]);
+    /// let mut b = MapPoint::new(2, [10.0, 0.0]);
+    /// a.connections.push((1, 2));
+    /// b.connections.push((1, 2));
+    ///
+    /// let mut map = Map::new();
+    /// map.add_points(vec![a, b]);
+    /// map.build_lines_from_connections();
+    /// assert_eq!(map.lines().count(), 1);
+    ///
+    /// map.move_point(2, [10.0, 10.0]);
+    /// let line = map.lines().next().unwrap();
+    /// assert!(line.point1 == [10.0, 10.0] || line.point2 == [10.0, 10.0]);
+    /// ```
+    pub fn build_lines_from_connections(&mut self) {
+        let _span = tracing::info_span!("build_lines_from_connections").entered();
+        let empty = HashMap::new();
+        let points = self.points.as_ref().unwrap_or(&empty);
+        let index = ConnectionIndex::new(points);
+        let segments = index
+            .pairs
+            .iter()
+            .map(|(id, (a, b))| MapSegment::new(*id, points[a].coords, points[b].coords))
+            .collect();
+        self.load_segments(segments);
+        self.line_load_issues.clear();
+        self.derived_lines = Some(index);
+    }
+
+    /// Updates the derived lines around a node whose connections went from
+    /// `old` to `new`; a removed node has no new connections.
+    fn derive_node_lines(
+        &mut self,
+        node_id: usize,
+        old: &[(usize, usize)],
+        new: &[(usize, usize)],
+    ) {
+        let Some(index) = self.derived_lines.as_mut() else {
+            return;
+        };
+        index.unlist(node_id, old);
+        index.list(node_id, new);
+        let affected: HashSet<(usize, usize)> =
+            index.touching(node_id).chain(old.iter().copied()).collect();
+        for connection in affected {
+            self.derive_line(connection);
+        }
+    }
+
+    /// Pairs a derived connection with its nodes again and redraws its line,
+    /// or drops the line if the connection no longer joins two nodes.
+    fn derive_line(&mut self, connection: (usize, usize)) {
+        let (Some(index), Some(points)) = (self.derived_lines.as_mut(), &self.points) else {
+            return;
+        };
+        let segment = index
+            .pair(points, connection)
+            .map(|(a, b)| MapSegment::new(connection, points[&a].coords, points[&b].coords));
+        match segment {
+            Some(segment) => self.insert_line(segment),
+            None => self.remove_line(connection),
+        };
+    }
+
+    /// Moves the end of the derived lines attached to a node that was just
+    /// moved.
+    fn move_derived_lines(&mut self, node_id: usize) {
+        let (Some(index), Some(points)) = (&self.derived_lines, &self.points) else {
+            return;
+        };
+        let moved: Vec<MapSegment> = index
+            .touching(node_id)
+            .filter_map(|connection| {
+                let (a, b) = *index.pairs.get(&connection)?;
+                (a == node_id || b == node_id)
+                    .then(|| MapSegment::new(connection, points[&a].coords, points[&b].coords))
+            })
+            .collect();
+        for segment in moved {
+            self.insert_line(segment);
+        }
+    }
+
+    /// Checks the loaded nodes and lines for inconsistencies.
+    ///
+    /// Reports connections without a line, lines no node lists, lines not
+    /// ending at their nodes, non-finite coordinates, and the repeated or
+    /// mismatched ids seen by the last [`Map::add_points`] /
+    /// [`Map::add_hashmap_points`] and [`Map::add_lines`] /
+    /// [`Map::add_hashmap_lines`] calls. An id stops being reported once that
+    /// node or line is inserted again or removed. This scans every node and
+    /// line, so run it after loading rather than every frame.
+    pub fn validate(&self) -> ValidationReport {
+        let _span = tracing::info_span!("validate").entered();
+        let empty = HashMap::new();
+        let points = self.points.as_ref().unwrap_or(&empty);
+        let mut issues = self.point_load_issues.clone();
+        issues.extend(self.line_load_issues.iter().cloned());
+        issues.extend(validation::check_graph(points, self.lines()));
+        ValidationReport { issues }
+    }
+
+    /// Strict version of [`Map::add_points`]: loads the nodes only if none of
+    /// them has a repeated id or a non-finite coordinate.
+    ///
+    /// On error nothing is loaded and the report lists the offending nodes.
+    pub fn try_add_points(&mut self, points: Vec<MapPoint>) -> Result<(), ValidationReport> {
+        let _span = tracing::info_span!("try_add_points").entered();
+        ValidationReport {
+            issues: validation::check_points(&points),
+        }
+        .into_result()?;
+        self.add_points(points);
+        Ok(())
+    }
+
+    /// Strict version of [`Map::add_lines`]: loads the lines only if they are
+    /// consistent with the loaded nodes (see [`Map::validate`]).
+    ///
+    /// Load the nodes first. On error nothing is loaded and the report lists
+    /// every problem found, including the nodes' connections that would be
+    /// left without a line.
+    pub fn try_add_lines(&mut self, segments: Vec<MapSegment>) -> Result<(), ValidationReport> {
+        let _span = tracing::info_span!("try_add_lines").entered();
+        let empty = HashMap::new();
+        let points = self.points.as_ref().unwrap_or(&empty);
+        let mut issues = validation::check_segment_ids(&segments);
+        issues.extend(validation::check_graph(points, &segments));
+        ValidationReport { issues }.into_result()?;
+        self.add_lines(segments);
+        Ok(())
+    }
+
+    /// Adds a single line, or replaces the line with the same
+    /// [`MapSegment::id`], and returns the line it replaced.
+    ///
+    /// The segment is inserted into the existing R-tree instead of rebuilding
+    /// it, so toggling a few connections is cheap. As with
+    /// [`Map::add_lines`], the endpoint nodes should list the id in their
+    /// [`MapPoint::connections`].
+    pub fn insert_line(&mut self, segment: MapSegment) -> Option<MapSegment> {
+        let _span = tracing::info_span!("insert_line").entered();
+        let previous = self.remove_line(segment.id);
+        self.forget_line_load_issues(segment.id);
+        self.segments
+            .get_or_insert_with(rstar::RTree::new)
+            .insert(segment);
+        self.segments_by_id.insert(segment.id, segment);
+        previous
+    }
+
+    /// Replaces an already loaded line with `segment` (matched by id) and
+    /// returns the previous version.
+    ///
+    /// Does nothing and returns `None` if no line with that id is loaded; use
+    /// [`Map::insert_line`] to add new lines.
+    pub fn update_line(&mut self, segment: MapSegment) -> Option<MapSegment> {
+        let _span = tracing::info_span!("update_line").entered();
+        let previous = self.remove_line(segment.id)?;
+        self.insert_line(segment);
+        Some(previous)
+    }
+
+    /// Removes the line with the given id and returns it.
+    pub fn remove_line(&mut self, id: (usize, usize)) -> Option<MapSegment> {
+        let _span = tracing::info_span!("remove_line").entered();
+        self.forget_line_load_issues(id);
+        let segment = self.segments_by_id.remove(&id)?;
+        self.segments.as_mut()?.remove(&segment)
+    }
+
+    /// Replaces the lines and their id index with `segments`. Of several
+    /// lines with the same id only the last one is kept.
+    fn load_segments(&mut self, segments: Vec<MapSegment>) {
+        self.segments_by_id = segments
+            .into_iter()
+            .map(|segment| (segment.id, segment))
+            .collect();
+        self.segments = Some(rstar::RTree::bulk_load(
+            self.segments_by_id.values().copied().collect(),
+        ));
+    }
+
+    /// Drops the load issues about the node `id`, which was just replaced or
+    /// removed.
+    fn forget_point_load_issues(&mut self, node_id: usize) {
+        self.point_load_issues.retain(|issue| match issue {
+            ValidationIssue::DuplicateNodeId { id } => *id != node_id,
+            ValidationIssue::MismatchedNodeKey { key, id } => *key != node_id && *id != node_id,
+            _ => true,
+        });
+    }
+
+    /// Drops the load issues about the line `id`, which was just replaced or
+    /// removed.
+    fn forget_line_load_issues(&mut self, line_id: (usize, usize)) {
+        self.line_load_issues.retain(|issue| match issue {
+            ValidationIssue::DuplicateSegmentId { id } => *id != line_id,
+            ValidationIssue::MismatchedSegmentKey { key, id } => *key != line_id && *id != line_id,
+            _ => true,
+        });
+    }
+
+    /// Returns every loaded line, in no particular order.
+    pub fn lines(&self) -> impl Iterator<Item = &MapSegment> {
+        self.segments.iter().flat_map(|tree| tree.iter())
     }
 
     fn adjust_bounds(&mut self) {
@@ -565,44 +1303,231 @@ impl Map {
         self.current.pos = self.reference.pos * self.zoom;
     }
 
-    fn capture_mouse_events(&mut self, ui: &Ui, _resp: &Response) {
+    /// Handles the drag gesture: pans the view or, when a selection gesture
+    /// is in progress (see [`MapSettings::drag_behavior`]), extends the
+    /// selection shape and applies it on release.
+    fn capture_drag_events(&mut self, ui: &Ui, resp: &Response) -> Vec<MapEvent> {
+        let _span = tracing::info_span!("capture_drag_events").entered();
+        let mut events = Vec::new();
+
+        if resp.drag_started_by(PointerButton::Primary)
+            && self.drag_selects(ui)
+            && let Some(origin) = ui.input(|x| x.pointer.press_origin())
+        {
+            self.selection_gesture = Some(vec![self.screen_to_map_point(origin)]);
+        }
+
+        if self.selection_gesture.is_some() {
+            if let Some(pos) = resp.interact_pointer_pos() {
+                let point = self.screen_to_map_point(pos);
+                let shape = self.settings.selection_shape.clone();
+                if let Some(gesture) = self.selection_gesture.as_mut() {
+                    match shape {
+                        SelectionShape::Rectangle => {
+                            gesture.truncate(1);
+                            gesture.push(point);
+                        }
+                        SelectionShape::Lasso => {
+                            if gesture.last() != Some(&point) {
+                                gesture.push(point);
+                            }
+                        }
+                    }
+                }
+            }
+            if resp.drag_stopped() {
+                let gesture = self.selection_gesture.take().unwrap_or_default();
+                let previous = self.selection.clone();
+                let nodes = self.nodes_in_selection_shape(&gesture);
+                if !ui.input(|x| x.modifiers.ctrl || x.modifiers.command) {
+                    self.selection.clear();
+                }
+                self.selection.extend(nodes);
+                if self.selection != previous {
+                    events.push(MapEvent::SelectionChanged);
+                }
+            }
+        } else if ui.input(|x| x.multi_touch().is_none()) {
+            // multi-touch gestures pan through `capture_mouse_events`
+            self.pan_by(resp.drag_delta());
+        }
+        events
+    }
+
+    /// Moves the view so the content follows a screen displacement of
+    /// `delta` points.
+    fn pan_by(&mut self, delta: Vec2) {
+        if delta.length() != 0.0 {
+            let _span = tracing::info_span!("calculating_points_in_visible_area").entered();
+
+            let coords = RawPoint::from(delta.to_pos2());
+            let new_pos = self.reference.pos - (coords / self.zoom);
+            self.set_pos(new_pos.into());
+        }
+    }
+
+    /// Returns `true` if a drag starting now should draw a selection shape
+    /// instead of panning.
+    fn drag_selects(&self, ui: &Ui) -> bool {
+        let shift = ui.input(|x| x.modifiers.shift);
+        self.settings.selection_mode == SelectionMode::Multiple
+            && (self.settings.drag_behavior == DragBehavior::Select) != shift
+    }
+
+    /// Returns the nodes inside the selection shape `gesture`, given in map
+    /// coordinates.
+    fn nodes_in_selection_shape(&self, gesture: &[RawPoint]) -> Vec<usize> {
+        if gesture.len() < 2 {
+            return Vec::new();
+        }
+        let mut min = RawPoint::new(f32::INFINITY, f32::INFINITY);
+        let mut max = RawPoint::new(f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for point in gesture {
+            for i in 0..min.components.len() {
+                min.components[i] = min.components[i].min(point.components[i]);
+                max.components[i] = max.components[i].max(point.components[i]);
+            }
+        }
+        let candidates = self.nodes_in_bounds(min, max);
+        match self.settings.selection_shape {
+            SelectionShape::Rectangle => candidates,
+            SelectionShape::Lasso => {
+                let points = self.points.as_ref();
+                candidates
+                    .into_iter()
+                    .filter(|id| {
+                        points
+                            .and_then(|hash_map| hash_map.get(id))
+                            .is_some_and(|point| {
+                                polygon_contains(gesture, RawPoint::from(point.coords))
+                            })
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Returns the nodes whose coordinates lie inside the axis-aligned box
+    /// spanning from `min` to `max`, in map coordinates.
+    fn nodes_in_bounds(&self, min: RawPoint, max: RawPoint) -> Vec<usize> {
+        self.tree
+            .as_ref()
+            .and_then(|tree| tree.bounding_box(&min.components, &max.components).ok())
+            .map(|ids| {
+                ids.into_iter()
+                    .copied()
+                    .filter(|id| self.is_id_shown(*id))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn capture_mouse_events(&mut self, ui: &Ui, resp: &Response) -> Vec<MapEvent> {
         let _span = tracing::info_span!("capture_mouse_events").entered();
+        let mut events = Vec::new();
+
+        // Hit-testing runs before any zoom change so it matches what was
+        // painted this frame.
+        let hovered = resp.hover_pos().and_then(|pos| self.node_at(pos));
+        if hovered != self.hovered_node {
+            if let Some(id) = self.hovered_node {
+                events.push(MapEvent::NodeHoverLeft { id });
+            }
+            if let Some(id) = hovered {
+                events.push(MapEvent::NodeHoverEntered { id });
+            }
+            self.hovered_node = hovered;
+        }
+        if resp.clicked()
+            && let Some(pos) = resp.interact_pointer_pos()
+            && let Some(index) = self.cluster_at(pos)
+        {
+            let members = self.clusters[index].members.clone();
+            self.fit_nodes(&members, Self::CLUSTER_FIT_PADDING);
+            events.push(MapEvent::ClusterClicked { members });
+        } else if resp.clicked()
+            && let Some(pos) = resp.interact_pointer_pos()
+            && self.node_at(pos).is_none()
+            && let Some(shape) = self.group_at(pos)
+        {
+            let (id, center) = (shape.id, shape.center);
+            let zoom = self.node_groups[&id].expand_zoom;
+            self.set_pos(center);
+            self.set_zoom(zoom.clamp(self.settings.min_zoom, self.settings.max_zoom));
+            events.push(MapEvent::GroupClicked { id });
+        } else if resp.clicked()
+            && let Some(pos) = resp.interact_pointer_pos()
+        {
+            let clicked_node = self.node_at(pos);
+            if let Some(id) = clicked_node {
+                events.push(MapEvent::NodeClicked { id });
+                if resp.double_clicked() {
+                    events.push(MapEvent::NodeDoubleClicked { id });
+                }
+            } else if let Some(id) = self.segment_at(pos) {
+                events.push(MapEvent::SegmentClicked { id });
+            } else {
+                events.push(MapEvent::BackgroundClicked {
+                    position: self.screen_to_map_point(pos).into(),
+                });
+            }
+            let modifiers = ui.input(|x| x.modifiers);
+            if self.select_on_click(clicked_node, modifiers) {
+                events.push(MapEvent::SelectionChanged);
+            }
+        }
+
         // capture MouseWheel Event for Zoom control change
         if ui.rect_contains_pointer(self.map_area) {
+            let line_scroll_speed = ui.ctx().options(|o| o.input_options.line_scroll_speed);
             ui.input(|x| {
                 let _span = tracing::info_span!("capture_mouse_events").entered();
+                // keep the map point under the pointer fixed
+                let anchor = x.pointer.hover_pos().unwrap_or(self.map_area.center());
 
                 if !x.events.is_empty() {
                     for event in &x.events {
                         match event {
                             Event::MouseWheel {
-                                unit: _,
+                                unit,
                                 delta,
                                 modifiers,
                                 phase: _,
                             } => {
+                                if self.settings.scroll_action == ScrollAction::Pan
+                                    && !modifiers.command
+                                {
+                                    let points = match unit {
+                                        MouseWheelUnit::Point => *delta,
+                                        MouseWheelUnit::Line => *delta * line_scroll_speed,
+                                        MouseWheelUnit::Page => *delta * self.map_area.height(),
+                                    };
+                                    self.pan_by(points);
+                                    continue;
+                                }
+                                // in pan mode the modifier is what makes the wheel zoom,
+                                // so it does not speed it up
+                                let faster = self.settings.scroll_action == ScrollAction::Zoom;
+
                                 #[cfg(target_os = "macos")]
-                                let zoom_modifier = if modifiers.mac_cmd {
+                                let zoom_modifier = if faster && modifiers.mac_cmd {
                                     delta.y / 80.00
                                 } else {
                                     delta.y / 400.00
                                 };
 
                                 #[cfg(not(target_os = "macos"))]
-                                let zoom_modifier = if modifiers.ctrl {
+                                let zoom_modifier = if faster && modifiers.ctrl {
                                     delta.y / 8.00
                                 } else {
                                     delta.y / 40.00
                                 };
 
-                                let mut pre_zoom = self.zoom + zoom_modifier;
-                                if pre_zoom > self.settings.max_zoom {
-                                    pre_zoom = self.settings.max_zoom;
-                                }
-                                if pre_zoom < self.settings.min_zoom {
-                                    pre_zoom = self.settings.min_zoom;
-                                }
-                                self.zoom = pre_zoom;
+                                self.set_zoom_at(self.zoom + zoom_modifier, anchor);
+                            }
+                            // trackpad pinch
+                            Event::Zoom(factor) => {
+                                self.set_zoom_at(self.zoom * factor, anchor);
                             }
                             _ => {
                                 continue;
@@ -610,25 +1535,331 @@ impl Map {
                         };
                     }
                 }
+
+                // touch screen pinch and two-finger pan
+                if let Some(touch) = x.multi_touch() {
+                    self.pan_by(touch.translation_delta);
+                    self.set_zoom_at(self.zoom * touch.zoom_delta, touch.center_pos);
+                }
             });
         }
+        events
     }
 
-    /// Sets the zoom factor.
-    ///
-    /// Values outside the [`MapSettings::min_zoom`]..=[`MapSettings::max_zoom`]
-    /// range are ignored.
-    pub fn set_zoom(&mut self, value: f32) {
-        if value >= self.settings.min_zoom && value <= self.settings.max_zoom {
-            self.zoom = value;
+    /// Applies [`MapSettings::selection_mode`] to a click on `node` (`None` for
+    /// the background). Returns `true` if the selection changed.
+    fn select_on_click(&mut self, node: Option<usize>, modifiers: Modifiers) -> bool {
+        let previous = self.selection.clone();
+        let toggle = modifiers.ctrl || modifiers.command || modifiers.shift;
+        match (&self.settings.selection_mode, node) {
+            (SelectionMode::Disabled, _) => {}
+            (SelectionMode::Multiple, Some(id)) if toggle => self.toggle_node_selection(id),
+            (SelectionMode::Multiple, None) if toggle => {}
+            (_, Some(id)) => self.set_selection([id]),
+            (_, None) => self.clear_selection(),
         }
+        self.selection != previous
     }
 
-    /// Returns the current zoom factor.
+    /// Returns the map-to-screen transform of the current view.
+    fn map_transform(&self) -> MapTransform {
+        MapTransform {
+            zoom: self.zoom,
+            origin: self.viewport_origin(),
+        }
+    }
+
+    /// Returns the screen position of the map origin for the current view:
+    /// `screen = map * zoom - origin`.
+    fn viewport_origin(&self) -> RawPoint {
+        self.current.pos - RawPoint::from(self.map_area.center())
+    }
+
+    fn screen_to_map_point(&self, position: Pos2) -> RawPoint {
+        (self.viewport_origin() + RawPoint::from(position)) / self.zoom
+    }
+
+    /// Returns the id of the node closest to `map_point`, optionally limited
+    /// to those within `max_distance` map units.
+    ///
+    /// Nodes on hidden layers are skipped.
+    fn nearest_node(&self, map_point: RawPoint, max_distance: Option<f32>) -> Option<usize> {
+        let tree = self.tree.as_ref()?;
+        let max_distance = max_distance.map(|max| max.powi(2));
+        tree.iter_nearest(&map_point.components, &squared_euclidean)
+            .ok()?
+            .take_while(|(distance, _)| max_distance.is_none_or(|max| *distance <= max))
+            .map(|(_, id)| *id)
+            .find(|id| self.is_id_shown(*id))
+    }
+
+    /// Returns the registered layer of `point`, if any.
+    fn layer_of(&self, point: &MapPoint) -> Option<&NodeLayer> {
+        point
+            .layer
+            .as_ref()
+            .and_then(|name| self.node_layers.get(name))
+    }
+
+    /// Returns `false` if `point` belongs to a layer that is hidden at the
+    /// current zoom.
+    fn is_node_shown(&self, point: &MapPoint) -> bool {
+        self.layer_of(point)
+            .is_none_or(|layer| layer.is_shown(self.zoom))
+    }
+
+    fn is_id_shown(&self, node_id: usize) -> bool {
+        self.node_layers.is_empty()
+            || self
+                .point(node_id)
+                .is_none_or(|point| self.is_node_shown(point))
+    }
+
+    /// Returns the template that draws `point`: its layer's one, or else the
+    /// map-wide one.
+    fn template_for(&self, point: &MapPoint) -> Option<&Rc<dyn NodeTemplate>> {
+        self.layer_of(point)
+            .and_then(|layer| layer.template.as_ref())
+            .or(self.node_template.as_ref())
+    }
+
+    /// Returns the node under the screen position `position`, if any.
+    fn node_at(&self, position: Pos2) -> Option<usize> {
+        if self.cluster_at(position).is_some() {
+            return None;
+        }
+        // The pick radius scales with the zoom just like the node shapes, so
+        // in map units it is constant.
+        self.nearest_node(
+            self.screen_to_map_point(position),
+            Some(self.settings.pick_radius),
+        )
+        .filter(|id| !self.aggregated.contains(id))
+    }
+
+    /// Returns the index of the cluster drawn under the screen position
+    /// `position`, if any.
+    fn cluster_at(&self, position: Pos2) -> Option<usize> {
+        let transform = self.map_transform();
+        self.clusters.iter().position(|cluster| {
+            let radius = match &self.cluster_template {
+                Some(template) => template.hit_radius(self.zoom, cluster),
+                None => cluster_glyph_radius(cluster.members.len()),
+            };
+            transform.map_to_screen(cluster.center).distance(position) <= radius
+        })
+    }
+
+    /// Returns the collapsed group drawn under the screen position
+    /// `position`, if any.
+    fn group_at(&self, position: Pos2) -> Option<&GroupShape> {
+        let transform = self.map_transform();
+        self.group_view.as_ref()?.shapes.iter().find(|shape| {
+            transform.map_to_screen(shape.center).distance(position)
+                <= self.group_glyph_radius(shape)
+        })
+    }
+
+    /// Returns the visible line under the screen position `position`, if any.
+    fn segment_at(&self, position: Pos2) -> Option<(usize, usize)> {
+        if self.zoom <= self.settings.line_visible_zoom || self.current_style().line.is_none() {
+            return None;
+        }
+        self.line_at(
+            self.screen_to_map_point(position).into(),
+            self.settings.pick_radius,
+        )
+    }
+
+    /// Sets the zoom factor.
+    ///
+    /// Values outside the [`MapSettings::min_zoom`]..=[`MapSettings::max_zoom`]
+    /// range are ignored. A valid value cancels any transition started with
+    /// [`Map::fly_to`].
+    pub fn set_zoom(&mut self, value: f32) {
+        if value >= self.settings.min_zoom && value <= self.settings.max_zoom {
+            self.flight = None;
+            self.zoom = value;
+        }
+    }
+
+    /// Sets the zoom factor while keeping the map point under the screen
+    /// position `position` fixed, as mouse wheel zooming does.
+    ///
+    /// Unlike [`Map::set_zoom`], values outside the
+    /// [`MapSettings::min_zoom`]..=[`MapSettings::max_zoom`] range are clamped
+    /// to it. `position` is in screen coordinates, e.g. the pointer position;
+    /// before the widget has been rendered once the view center is used
+    /// instead. Cancels any transition started with [`Map::fly_to`].
+    pub fn set_zoom_at(&mut self, value: f32, position: Pos2) {
+        let _span = tracing::info_span!("set_zoom_at").entered();
+        self.flight = None;
+        let zoom = value.clamp(self.settings.min_zoom, self.settings.max_zoom);
+        let anchor = if self.map_area.is_positive() {
+            RawPoint::from(position) - RawPoint::from(self.map_area.center())
+        } else {
+            RawPoint::default()
+        };
+        // map point under `position`, which must stay under it after zooming
+        let map_point = self.reference.pos + anchor / self.zoom;
+        self.zoom = zoom;
+        self.reference.pos = map_point - anchor / zoom;
+        self.adjust_bounds();
+        self.calculate_visible_points();
+    }
+
+    /// Returns the current zoom factor.
     pub fn get_zoom(&mut self) -> f32 {
         self.zoom
     }
 
+    /// Smoothly moves the view to center on `position` at `zoom` over
+    /// `duration`.
+    ///
+    /// The transition advances every frame while the widget is shown, which
+    /// requests repaints on its own until it arrives. It is cancelled as soon
+    /// as the user pans or zooms, by a call to [`Map::set_pos`],
+    /// [`Map::set_pos_from_nodeid`] or [`Map::set_zoom`], or replaced by
+    /// another `fly_to`. `zoom` is clamped to the
+    /// [`MapSettings::min_zoom`]..=[`MapSettings::max_zoom`] range, and a zero
+    /// `duration` jumps straight to the destination.
+    pub fn fly_to(&mut self, position: [f32; 2], zoom: f32, duration: Duration, easing: Easing) {
+        let _span = tracing::info_span!("fly_to").entered();
+        let zoom = zoom.clamp(self.settings.min_zoom, self.settings.max_zoom);
+        self.flight = Some(Flight::new(
+            (self.reference.pos, self.zoom),
+            (RawPoint::from(position), zoom),
+            Instant::now(),
+            duration,
+            easing,
+        ));
+        if duration.is_zero() {
+            self.advance_flight(Instant::now());
+        }
+    }
+
+    /// Smoothly centers the view on the node with the given id, keeping the
+    /// current zoom. See [`Map::fly_to`].
+    ///
+    /// Does nothing if no points have been loaded yet or if `node_id` is
+    /// unknown.
+    pub fn fly_to_node(&mut self, node_id: usize, duration: Duration, easing: Easing) {
+        let _span = tracing::info_span!("fly_to_node").entered();
+        if let Some(coords) = self
+            .points
+            .as_ref()
+            .and_then(|points| points.get(&node_id))
+            .map(|point| point.coords)
+        {
+            self.fly_to(coords, self.zoom, duration, easing);
+        }
+    }
+
+    /// Centers the view on the map rectangle spanned by `min` and `max` and
+    /// picks the zoom that makes it fill the widget.
+    ///
+    /// The zoom is clamped to the
+    /// [`MapSettings::min_zoom`]..=[`MapSettings::max_zoom`] range, so very
+    /// large or very small rectangles may not fit exactly. If the widget has
+    /// not been rendered yet, the fit is applied on the next frame, once its
+    /// size is known.
+    pub fn fit_bounds(&mut self, min: [f32; 2], max: [f32; 2]) {
+        let _span = tracing::info_span!("fit_bounds").entered();
+        self.fit_rect(RawPoint::from(min), RawPoint::from(max), 0.0);
+    }
+
+    /// Frames the given nodes, leaving `padding` screen points between them
+    /// and the widget border. See [`Map::fit_bounds`].
+    ///
+    /// Unknown ids are ignored; does nothing if none of the ids is known. A
+    /// single node is centered without changing the zoom.
+    pub fn fit_nodes(&mut self, node_ids: &[usize], padding: f32) {
+        let _span = tracing::info_span!("fit_nodes").entered();
+        let Some(points) = &self.points else {
+            return;
+        };
+        let mut bounds: Option<(RawPoint, RawPoint)> = None;
+        for coords in node_ids
+            .iter()
+            .filter_map(|id| points.get(id))
+            .map(|point| point.coords)
+        {
+            let (min, max) = bounds.get_or_insert((coords.into(), coords.into()));
+            for (axis, value) in coords.into_iter().enumerate() {
+                min.components[axis] = min.components[axis].min(value);
+                max.components[axis] = max.components[axis].max(value);
+            }
+        }
+        if let Some((min, max)) = bounds {
+            self.fit_rect(min, max, padding);
+        }
+    }
+
+    /// Frames every loaded node. See [`Map::fit_bounds`].
+    ///
+    /// Does nothing if no points have been loaded yet.
+    pub fn fit_all(&mut self) {
+        let _span = tracing::info_span!("fit_all").entered();
+        if self.points.is_some() {
+            self.fit_rect(self.reference.min, self.reference.max, 0.0);
+        }
+    }
+
+    fn fit_rect(&mut self, min: RawPoint, max: RawPoint, padding: f32) {
+        if !self.map_area.is_positive() {
+            self.pending_fit = Some((min, max, padding));
+            return;
+        }
+        self.flight = None;
+        let extent = (max - min).components;
+        let available = (self.map_area.size() - Vec2::splat(2.0 * padding)).max(Vec2::splat(1.0));
+        let fit_x = available.x / extent[0].abs();
+        let fit_y = available.y / extent[1].abs();
+        // a degenerate rectangle gives infinite zoom on that axis
+        let zoom = fit_x.min(fit_y);
+        if zoom.is_finite() {
+            self.zoom = zoom.clamp(self.settings.min_zoom, self.settings.max_zoom);
+        }
+        self.reference.pos = RawLine::new(min, max).midpoint();
+        self.adjust_bounds();
+        self.calculate_visible_points();
+    }
+
+    /// Returns `true` while a transition started with [`Map::fly_to`] is
+    /// still playing.
+    pub fn is_flying(&self) -> bool {
+        self.flight.is_some()
+    }
+
+    /// Moves the view to where the current flight is at `now`. Returns `true`
+    /// while the flight has not arrived yet.
+    fn advance_flight(&mut self, now: Instant) -> bool {
+        let Some(flight) = &self.flight else {
+            return false;
+        };
+        let (pos, zoom, done) = flight.at(now);
+        self.reference.pos = pos;
+        self.zoom = zoom;
+        self.adjust_bounds();
+        self.calculate_visible_points();
+        if done {
+            self.flight = None;
+        }
+        !done
+    }
+
+    /// Returns `true` if the user is panning or zooming the map this frame.
+    fn camera_input(&self, ui: &Ui, resp: &Response) -> bool {
+        resp.dragged()
+            || (ui.rect_contains_pointer(self.map_area)
+                && ui.input(|x| {
+                    x.multi_touch().is_some()
+                        || x.events
+                            .iter()
+                            .any(|e| matches!(e, Event::MouseWheel { .. } | Event::Zoom(_)))
+                }))
+    }
+
     /// Returns the style for the current theme, falling back to the first
     /// style if the current theme index has no entry.
     fn current_style(&self) -> &MapStyle {
@@ -742,45 +1973,75 @@ impl Map {
 
         let sub_rect = egui::Rect::from_two_pos(pos1, pos2);
         let ui_builder = egui::UiBuilder::new().clone().max_rect(sub_rect);
-        ui_obj.scope_builder(ui_builder, |ui_obj| {
-            ui_obj.add(zoom_slider);
-        });
+        let slider = ui_obj.scope_builder(ui_builder, |ui_obj| ui_obj.add(zoom_slider));
+        if slider.inner.changed() {
+            self.flight = None;
+        }
+
+        if self.settings.search_box != SearchBox::Hidden {
+            self.paint_search_box(ui_obj, rect);
+        }
+    }
+
+    fn paint_search_box(&mut self, ui_obj: &mut Ui, rect: Rect) {
+        let _span = tracing::info_span!("map_ui_paint_search_box").entered();
+        let box_rect = Rect::from_min_size(rect.left_top() + vec2(10.0, 10.0), vec2(220.0, 260.0));
+        let ui_builder = egui::UiBuilder::new().max_rect(box_rect);
+        let picked = ui_obj
+            .scope_builder(ui_builder, |ui_obj| {
+                Frame::popup(ui_obj.style())
+                    .show(ui_obj, |ui_obj| {
+                        let edit = ui_obj.add(
+                            TextEdit::singleline(&mut self.search_query)
+                                .hint_text("Search nodes")
+                                .desired_width(200.0),
+                        );
+                        if edit.changed() {
+                            self.refresh_search_results();
+                        }
+                        let mut picked = None;
+                        if edit.lost_focus() && ui_obj.input(|x| x.key_pressed(Key::Enter)) {
+                            picked = self.search_results.first().map(|found| found.id);
+                        }
+                        for found in &self.search_results {
+                            if ui_obj.selectable_label(false, &found.name).clicked() {
+                                picked = Some(found.id);
+                            }
+                        }
+                        picked
+                    })
+                    .inner
+            })
+            .inner;
+
+        if let Some(node_id) = picked {
+            match self.settings.search_box {
+                SearchBox::Fly(duration) => self.fly_to_node(node_id, duration, Easing::EaseInOut),
+                _ => self.set_pos_from_nodeid(node_id),
+            }
+            self.search_query.clear();
+            self.search_results.clear();
+        }
     }
 
     fn paint_map_points(
         &self,
-        vec_points: &Vec<isize>,
-        hashm: &Option<HashMap<usize, MapPoint>>,
+        vec_points: &[isize],
         paint: &Painter,
         ui_obj: &mut Ui,
         min_point: &RawPoint,
-        resp: &Response,
+        nearest_id: Option<usize>,
+        placement: Option<&LabelPlacement>,
     ) -> Result<Vec<usize>, ()> {
-        let mut nearest_id = None;
         let mut nodes_to_remove = Vec::new();
         let mut shape_vec = vec![];
 
-        if hashm.is_none() {
+        let Some(hash_map) = &self.points else {
             return Err(());
-        }
+        };
         if vec_points.is_empty() {
             return Err(());
         }
-        // detecting the nearest hover node
-        if self.settings.node_text_visibility == VisibilitySetting::Hover
-            && resp.hovered()
-            && let Some(point) = resp.hover_pos()
-        {
-            let raw_point = RawPoint::from(point);
-            let hovered_map_point = (*min_point + raw_point) / self.zoom;
-            if let Ok(nearest_node) = self.tree.as_ref().unwrap().nearest(
-                &hovered_map_point.components,
-                1,
-                &squared_euclidean,
-            ) {
-                nearest_id = Some(nearest_node.first().unwrap().1);
-            }
-        }
         // filling text settings
         let mut text_settings = TextSettings {
             size: 12.00 * self.zoom,
@@ -791,58 +2052,90 @@ impl Map {
             text_color: ui_obj.visuals().text_color(),
         };
 
+        // Sorting the visible nodes by layer, hidden layers are skipped
+        let mut nodes: Vec<(i32, &MapPoint)> = vec_points
+            .iter()
+            .filter_map(|id| hash_map.get(&id.cast_unsigned()))
+            .filter(|point| !self.aggregated.contains(&point.id))
+            .filter_map(|point| match self.layer_of(point) {
+                Some(layer) if !layer.is_shown(self.zoom) => None,
+                layer => Some((layer.map_or(0, |layer| layer.z_order), point)),
+            })
+            .collect();
+        if !self.node_layers.is_empty() {
+            nodes.sort_by_key(|(z_order, _)| *z_order);
+        }
+
         // Drawing Points
-        for temp_point in vec_points {
-            let parsed_point = temp_point.cast_unsigned();
-            if let Some(system) = hashm.as_ref().unwrap().get(&parsed_point) {
-                let _span = tracing::info_span!("painting_points_m").entered();
-                let viewport_point = RawPoint::from(system.coords) * self.zoom - min_point;
-                if let Some(node_template) = &self.node_template {
-                    if nearest_id.unwrap_or(&0usize) == &system.get_id() {
-                        node_template.selection_ui(ui_obj, viewport_point.into(), self.zoom);
-                    }
-                } else if self.zoom > self.settings.label_visible_zoom
-                    && self.settings.node_text_visibility == VisibilitySetting::Always
-                    || (self.settings.node_text_visibility == VisibilitySetting::Hover
-                        && nearest_id.unwrap_or(&0usize) == &system.get_id())
-                {
-                    let mut viewport_text = viewport_point;
-                    viewport_text.components[0] += 3.0 * self.zoom;
-                    viewport_text.components[1] -= 3.0 * self.zoom;
-                    text_settings.position = viewport_text;
+        let mut current_z = nodes.first().map_or(0, |(z_order, _)| *z_order);
+        for (z_order, system) in nodes {
+            let _span = tracing::info_span!("painting_points_m").entered();
+            if z_order != current_z {
+                // keep the built-in shapes below the next layer
+                paint.extend(std::mem::take(&mut shape_vec));
+                current_z = z_order;
+            }
+            let node_template = self.template_for(system);
+            let viewport_point = RawPoint::from(system.coords) * self.zoom - min_point;
+            let selected = self.selection.contains(&system.get_id());
+            if let Some(node_template) = node_template {
+                if selected {
+                    node_template.selected_ui(ui_obj, viewport_point.into(), self.zoom);
+                }
+                if nearest_id == Some(system.get_id()) {
+                    node_template.selection_ui(ui_obj, viewport_point.into(), self.zoom);
+                }
+            } else if self.shows_node_name(system, nearest_id) {
+                // without decluttering every name takes the preferred spot
+                let anchor = match placement {
+                    Some(placement) => placement.node_names.get(&system.get_id()).copied(),
+                    None => Some(NODE_NAME_ANCHORS[0]),
+                };
+                if let Some(anchor) = anchor {
+                    let position =
+                        node_name_position(viewport_point.into(), anchor, 3.0 * self.zoom);
+                    text_settings.position = RawPoint::from(position);
+                    text_settings.anchor = anchor;
                     text_settings.text = system.get_name();
                     self.paint_label(paint, &text_settings);
                 }
+            }
 
-                let system_id = system.get_id();
-                if let Some(init_time) = self.entities.get(&system_id) {
-                    if let Some(template) = &self.node_template {
-                        template.notification_ui(
-                            ui_obj,
-                            viewport_point.into(),
-                            self.zoom,
-                            *init_time,
-                            self.current_style().alert_color,
-                        );
-                    } else if Animation::pulse(
-                        paint,
-                        viewport_point,
+            let system_id = system.get_id();
+            if let Some(init_time) = self.entities.get(&system_id) {
+                if let Some(template) = node_template {
+                    template.notification_ui(
+                        ui_obj,
+                        viewport_point.into(),
                         self.zoom,
                         *init_time,
                         self.current_style().alert_color,
-                    ) {
-                        ui_obj.ctx().request_repaint();
-                    } else {
-                        nodes_to_remove.push(system_id);
-                    }
-                }
-                if let Some(node_template) = &self.node_template {
-                    node_template.node_ui(ui_obj, viewport_point.into(), self.zoom, system);
+                    );
+                } else if Animation::pulse(
+                    paint,
+                    viewport_point,
+                    self.zoom,
+                    *init_time,
+                    self.current_style().alert_color,
+                ) {
+                    ui_obj.ctx().request_repaint();
                 } else {
-                    shape_vec.push(Shape::circle_filled(
+                    nodes_to_remove.push(system_id);
+                }
+            }
+            if let Some(node_template) = node_template {
+                node_template.node_ui(ui_obj, viewport_point.into(), self.zoom, system);
+            } else {
+                shape_vec.push(Shape::circle_filled(
+                    viewport_point.into(),
+                    4.00 * self.zoom,
+                    self.node_fill_color(system.get_id()),
+                ));
+                if selected {
+                    shape_vec.push(Shape::circle_stroke(
                         viewport_point.into(),
-                        4.00 * self.zoom,
-                        self.current_style().fill_color,
+                        7.00 * self.zoom,
+                        Stroke::new(2.00 * self.zoom, self.current_style().selection_color),
                     ));
                 }
             }
@@ -883,7 +2176,11 @@ impl Map {
                 self.map_area.height() / 2.0 / self.zoom + padding,
             );
             let query = rstar::AABB::from_corners((center - half).into(), (center + half).into());
-            for segment in segments.locate_in_envelope_intersecting(query) {
+            let hidden = self.group_view.as_ref().map(|view| &view.hidden_segments);
+            for segment in segments
+                .locate_in_envelope_intersecting(query)
+                .filter(|segment| hidden.is_none_or(|hidden| !hidden.contains(&segment.id)))
+            {
                 let raw_line = segment.raw_line();
                 let pos_a = raw_line.points[0] * self.zoom - min_point;
                 let pos_b = raw_line.points[1] * self.zoom - min_point;
@@ -893,6 +2190,269 @@ impl Map {
         }
     }
 
+    fn paint_selection_gesture(&self, painter: &Painter, min_point: &RawPoint) {
+        if let Some(gesture) = &self.selection_gesture
+            && gesture.len() > 1
+        {
+            let color = self.current_style().selection_color;
+            let stroke = Stroke::new(1.0, color);
+            let to_screen = |point: &RawPoint| Pos2::from(*point * self.zoom - min_point);
+            match self.settings.selection_shape {
+                SelectionShape::Rectangle => {
+                    let rect = Rect::from_two_pos(to_screen(&gesture[0]), to_screen(&gesture[1]));
+                    painter.rect(
+                        rect,
+                        0.0,
+                        color.gamma_multiply(0.15),
+                        stroke,
+                        StrokeKind::Middle,
+                    );
+                }
+                SelectionShape::Lasso => {
+                    let outline = gesture.iter().map(to_screen).collect();
+                    painter.add(Shape::closed_line(outline, stroke));
+                }
+            }
+        }
+    }
+
+    /// Draws the lines of every route, whatever the zoom level.
+    fn paint_routes(&self, painter: &Painter, transform: &MapTransform, visible: Rect) {
+        let _span = tracing::info_span!("paint_routes").entered();
+        let Some(points) = &self.points else {
+            return;
+        };
+        let mut shape_vec = vec![];
+        for route in self.routes.values() {
+            for pair in route.nodes.windows(2) {
+                if let (Some(a), Some(b)) = (points.get(&pair[0]), points.get(&pair[1])) {
+                    let [start, end] = self.route_leg(a, b);
+                    let bounds = Rect::from_two_pos(start.into(), end.into());
+                    if bounds.intersects(visible) {
+                        shape_vec.push(Shape::line_segment(
+                            [transform.map_to_screen(start), transform.map_to_screen(end)],
+                            route.style.stroke,
+                        ));
+                    }
+                }
+            }
+        }
+        painter.extend(shape_vec);
+    }
+
+    /// Returns where the route leg from `a` to `b` is drawn, in map
+    /// coordinates: along the line joining both nodes when one is loaded,
+    /// straight from node to node otherwise.
+    fn route_leg(&self, a: &MapPoint, b: &MapPoint) -> [[f32; 2]; 2] {
+        let joined = a
+            .connections
+            .iter()
+            .filter(|connection| b.connections.contains(connection))
+            .chain([&(a.id.min(b.id), a.id.max(b.id))])
+            .find_map(|connection| self.segments_by_id.get(connection));
+        let Some(segment) = joined else {
+            return [a.coords, b.coords];
+        };
+        let distance = |from: [f32; 2], to: [f32; 2]| (from[0] - to[0]).hypot(from[1] - to[1]);
+        // the segment may have been loaded in either direction
+        if distance(segment.point1, a.coords) <= distance(segment.point2, a.coords) {
+            [segment.point1, segment.point2]
+        } else {
+            [segment.point2, segment.point1]
+        }
+    }
+
+    /// Draws the numbered waypoint badges of every route, above the nodes.
+    fn paint_waypoints(&self, painter: &Painter, transform: &MapTransform, visible: Rect) {
+        let _span = tracing::info_span!("paint_waypoints").entered();
+        let Some(points) = &self.points else {
+            return;
+        };
+        for route in self.routes.values().filter(|route| route.style.numbered) {
+            for (index, point) in route
+                .nodes
+                .iter()
+                .enumerate()
+                .filter_map(|(index, id)| Some((index, points.get(id)?)))
+            {
+                if !visible.contains(point.coords.into()) {
+                    continue;
+                }
+                let center = transform.map_to_screen(point.coords);
+                painter.circle_filled(
+                    center,
+                    route.style.waypoint_radius,
+                    route.style.waypoint_color,
+                );
+                painter.text(
+                    center,
+                    Align2::CENTER_CENTER,
+                    (index + 1).to_string(),
+                    FontId::proportional(route.style.waypoint_radius * 1.3),
+                    route.style.number_color,
+                );
+            }
+        }
+    }
+
+    /// Returns the node whose name is shown because the pointer is over it,
+    /// with [`VisibilitySetting::Hover`].
+    fn name_hover_node(&self, resp: &Response) -> Option<usize> {
+        if self.settings.node_text_visibility == VisibilitySetting::Hover
+            && resp.hovered()
+            && let Some(point) = resp.hover_pos()
+        {
+            self.nearest_node(self.screen_to_map_point(point), None)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if the built-in renderer draws the name of `point`.
+    fn shows_node_name(&self, point: &MapPoint, name_hover: Option<usize>) -> bool {
+        self.zoom > self.settings.label_visible_zoom
+            && self.settings.node_text_visibility == VisibilitySetting::Always
+            || (self.settings.node_text_visibility == VisibilitySetting::Hover
+                && name_hover == Some(point.get_id()))
+    }
+
+    /// Decides which labels and node names have room this frame, and where
+    /// each node name goes.
+    fn place_labels(
+        &self,
+        paint: &Painter,
+        transform: &MapTransform,
+        name_hover: Option<usize>,
+        default_color: Color32,
+    ) -> LabelPlacement {
+        let _span = tracing::info_span!("place_labels").entered();
+        let mut requests = Vec::new();
+        for (index, label) in self.labels.iter().enumerate() {
+            if !label.is_shown(self.zoom, self.settings.line_visible_zoom) {
+                continue;
+            }
+            if let Some((galley, position, _)) =
+                self.layout_map_label(paint, transform, label, default_color)
+            {
+                let rect = label.anchor.anchor_size(position, galley.size());
+                requests.push(LabelRequest {
+                    key: LabelKey::Map(index),
+                    priority: label.priority,
+                    candidates: vec![rotated_bounds(rect, position, label.rotation)],
+                });
+            }
+        }
+
+        let font = FontId::proportional(12.00 * self.zoom);
+        for point in self
+            .visible_points
+            .iter()
+            .filter_map(|id| self.point(id.cast_unsigned()))
+        {
+            if !self.is_node_shown(point)
+                || self.aggregated.contains(&point.id)
+                || self.template_for(point).is_some()
+                || !self.shows_node_name(point, name_hover)
+            {
+                continue;
+            }
+            let size = paint
+                .layout_no_wrap(point.get_name(), font.clone(), default_color)
+                .size();
+            let node = transform.map_to_screen(point.coords);
+            let priority = if name_hover == Some(point.id) {
+                i32::MAX
+            } else if self.selection.contains(&point.id) {
+                i32::MAX - 1
+            } else {
+                0
+            };
+            requests.push(LabelRequest {
+                key: LabelKey::Node(point.id),
+                priority,
+                candidates: NODE_NAME_ANCHORS
+                    .iter()
+                    .map(|anchor| {
+                        let position = node_name_position(node, *anchor, 3.0 * self.zoom);
+                        anchor.anchor_size(position, size)
+                    })
+                    .collect(),
+            });
+        }
+
+        let mut placement = LabelPlacement::default();
+        for (key, candidate) in declutter::place(requests) {
+            match key {
+                LabelKey::Map(index) => {
+                    placement.map_labels.insert(index);
+                }
+                LabelKey::Node(id) => {
+                    placement
+                        .node_names
+                        .insert(id, NODE_NAME_ANCHORS[candidate]);
+                }
+            }
+        }
+        placement
+    }
+
+    /// Lays out a free-floating label, returning its text, screen position
+    /// and color, or `None` if it is too far off screen to be seen.
+    fn layout_map_label(
+        &self,
+        paint: &Painter,
+        transform: &MapTransform,
+        label: &MapLabel,
+        default_color: Color32,
+    ) -> Option<(Arc<Galley>, Pos2, Color32)> {
+        let font = label
+            .font
+            .clone()
+            .unwrap_or_else(|| FontId::proportional(12.00 * self.zoom * 2.00));
+        let color = label.color.unwrap_or(default_color);
+        let galley = paint.layout_no_wrap(label.text.clone(), font, color);
+        let position = transform.map_to_screen(label.position);
+        // any rotation keeps the text within its diagonal of the anchor
+        paint
+            .clip_rect()
+            .expand(galley.size().length())
+            .contains(position)
+            .then_some((galley, position, color))
+    }
+
+    fn paint_map_labels(
+        &self,
+        paint: &Painter,
+        transform: &MapTransform,
+        default_color: Color32,
+        placement: Option<&LabelPlacement>,
+    ) {
+        let _span = tracing::info_span!("paint_map_labels").entered();
+        let mut labels: Vec<&MapLabel> = self
+            .labels
+            .iter()
+            .enumerate()
+            .filter(|(index, label)| {
+                label.is_shown(self.zoom, self.settings.line_visible_zoom)
+                    && placement.is_none_or(|placement| placement.map_labels.contains(index))
+            })
+            .map(|(_, label)| label)
+            .collect();
+        // higher priorities are painted last, on top of the rest
+        labels.sort_by_key(|label| label.priority);
+        for label in labels {
+            if let Some((galley, position, color)) =
+                self.layout_map_label(paint, transform, label, default_color)
+            {
+                let rect = label.anchor.anchor_size(position, galley.size());
+                paint.add(
+                    epaint::TextShape::new(rect.min, galley, color)
+                        .with_angle_and_anchor(label.rotation, label.anchor),
+                );
+            }
+        }
+    }
+
     fn paint_label(&self, paint: &Painter, text_settings: &TextSettings) {
         let _span = tracing::info_span!("paint_label").entered();
         paint.text(
@@ -918,6 +2478,49 @@ impl Map {
             .or_insert(time);
     }
 
+    /// Converts a screen position, such as the pointer position, to map
+    /// coordinates.
+    ///
+    /// Uses the widget rectangle of the last frame drawn by [`Map::show`] and
+    /// the current zoom and position, so the result matches what is on screen
+    /// from the end of a frame until the view changes.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use egui_map::map::Map;
+    /// # fn ui(ui: &mut egui::Ui, map: &mut Map) {
+    /// map.show(ui);
+    /// if let Some(pointer) = ui.ctx().pointer_hover_pos() {
+    ///     let [x, y] = map.screen_to_map(pointer);
+    ///     ui.label(format!("{x:.1}, {y:.1}"));
+    /// }
+    /// # }
+    /// ```
+    pub fn screen_to_map(&self, position: Pos2) -> [f32; 2] {
+        let _span = tracing::info_span!("screen_to_map").entered();
+        self.map_transform().screen_to_map(position)
+    }
+
+    /// Converts map coordinates to a screen position; the inverse of
+    /// [`Map::screen_to_map`], valid under the same conditions.
+    pub fn map_to_screen(&self, point: [f32; 2]) -> Pos2 {
+        let _span = tracing::info_span!("map_to_screen").entered();
+        self.map_transform().map_to_screen(point)
+    }
+
+    /// Returns the part of the map shown by the widget, in map coordinates.
+    ///
+    /// Valid under the same conditions as [`Map::screen_to_map`]; before the
+    /// first frame the rectangle is empty.
+    pub fn visible_map_rect(&self) -> Rect {
+        let _span = tracing::info_span!("visible_map_rect").entered();
+        Rect::from_two_pos(
+            self.screen_to_map_point(self.map_area.min).into(),
+            self.screen_to_map_point(self.map_area.max).into(),
+        )
+    }
+
     /// Returns the id of the line closest to `point`, in map coordinates,
     /// when it lies within `tolerance` map units of the segment.
     ///
@@ -927,10 +2530,9 @@ impl Map {
     /// `None` when no lines are loaded or every segment is farther than
     /// `tolerance`. A negative `tolerance` behaves like `0.0`.
     ///
-    /// To hit-test a mouse click, convert the screen position to map
-    /// coordinates first (`map = (screen + origin) / zoom`, see the
-    /// [coordinate model](self#coordinate-model)) and pick a tolerance scaled
-    /// by `1.0 / zoom` so it stays constant in screen pixels.
+    /// To hit-test a mouse click, convert the screen position with
+    /// [`Map::screen_to_map`] first and pick a tolerance scaled by
+    /// `1.0 / zoom` so it stays constant in screen pixels.
     pub fn line_at(&self, point: [f32; 2], tolerance: f32) -> Option<(usize, usize)> {
         let _span = tracing::info_span!("line_at").entered();
         let segments = self.segments.as_ref()?;
@@ -950,36 +2552,582 @@ impl Map {
         closest.map(|(_, id)| id)
     }
 
-    /// Installs a right-click context menu whose contents are built by the
-    /// given [`ContextMenuManager`] implementation.
-    pub fn set_context_manager(&mut self, manager: Rc<dyn ContextMenuManager>) {
-        self.menu_manager = Some(manager);
+    /// Returns the nodes lying inside the rectangle spanned by the corners
+    /// `a` and `b`, in map coordinates and in no particular order.
+    ///
+    /// Like the other spatial queries, this answers from the node index and
+    /// includes nodes on hidden layers; use [`Map::visible_nodes`] for what
+    /// is on screen.
+    pub fn nodes_in_rect(&self, a: [f32; 2], b: [f32; 2]) -> Vec<usize> {
+        let _span = tracing::info_span!("nodes_in_rect").entered();
+        let (min, max) = rect_corners(a, b);
+        self.tree
+            .as_ref()
+            .and_then(|tree| tree.bounding_box(&min, &max).ok())
+            .map(|ids| ids.into_iter().copied().collect())
+            .unwrap_or_default()
     }
 
-    /// Replaces the built-in node rendering with a custom [`NodeTemplate`]
-    /// implementation.
-    ///
-    /// The template takes over the drawing of nodes, selection highlights,
-    /// notification animations and markers — including the node name labels,
-    /// which the widget no longer draws once a template is installed. See the
-    /// [`NodeTemplate`] examples for custom shapes and animations.
-    pub fn set_node_template(&mut self, template: Rc<dyn NodeTemplate>) {
-        self.node_template = Some(template);
+    /// Returns the nodes within `radius` map units of `center`, nearest
+    /// first.
+    pub fn nodes_within(&self, center: [f32; 2], radius: f32) -> Vec<usize> {
+        let _span = tracing::info_span!("nodes_within").entered();
+        let radius = radius.max(0.0).powi(2);
+        self.tree
+            .as_ref()
+            .and_then(|tree| tree.within(&center, radius, &squared_euclidean).ok())
+            .map(|mut found| {
+                found.sort_by(|a, b| a.0.total_cmp(&b.0));
+                found.into_iter().map(|(_, id)| *id).collect()
+            })
+            .unwrap_or_default()
     }
 
-    /// Adds the marker `id`, or moves it, so it points to the node `node_id`.
+    /// Returns up to `k` nodes closest to `point`, nearest first, optionally
+    /// limited to those within `max_distance` map units.
     ///
-    /// Markers are drawn as a blinking ring around the target node unless a
-    /// custom [`objects::NodeTemplate::marker_ui`] is installed.
-    pub fn update_marker(&mut self, id: usize, node_id: usize) {
-        self.markers
-            .entry(id)
-            .and_modify(|value| *value = node_id)
-            .or_insert(node_id);
+    /// # Examples
+    ///
+    /// ```
+    /// use egui_map::map::Map;
+    /// use egui_map::map::objects::MapPoint;
+    ///
+    /// let mut map = Map::new();
+    /// map.add_points(vec![
+    ///     MapPoint::new(1, [0.0, 0.0]),
+    ///     MapPoint::new(2, [3.0, 0.0]),
+    ///     MapPoint::new(3, [10.0, 0.0]),
+    /// ]);
+    /// assert_eq!(map.nearest_nodes([1.0, 0.0], 2, None), vec![1, 2]);
+    /// assert_eq!(map.nearest_nodes([1.0, 0.0], 5, Some(5.0)), vec![1, 2]);
+    /// ```
+    pub fn nearest_nodes(
+        &self,
+        point: [f32; 2],
+        k: usize,
+        max_distance: Option<f32>,
+    ) -> Vec<usize> {
+        let _span = tracing::info_span!("nearest_nodes").entered();
+        let Some(tree) = &self.tree else {
+            return Vec::new();
+        };
+        let max_distance = max_distance.map(|max| max.powi(2));
+        tree.iter_nearest(&point, &squared_euclidean)
+            .map(|nearest| {
+                nearest
+                    .take_while(|(distance, _)| max_distance.is_none_or(|max| *distance <= max))
+                    .take(k)
+                    .map(|(_, id)| *id)
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 
-    /// Sets the minimum width and/or height the widget should occupy, in egui
-    /// points. `None` leaves the corresponding dimension unconstrained.
+    /// Returns the lines crossing or lying inside the rectangle spanned by
+    /// the corners `a` and `b`, in map coordinates and in no particular
+    /// order.
+    pub fn lines_in_rect(&self, a: [f32; 2], b: [f32; 2]) -> Vec<(usize, usize)> {
+        let _span = tracing::info_span!("lines_in_rect").entered();
+        let Some(segments) = &self.segments else {
+            return Vec::new();
+        };
+        let (min, max) = rect_corners(a, b);
+        segments
+            .locate_in_envelope_intersecting(rstar::AABB::from_corners(min, max))
+            .filter(|segment| segment_hits_rect(segment.raw_line(), min, max))
+            .map(|segment| segment.id)
+            .collect()
+    }
+
+    /// Returns the nodes inside the area shown by the widget, skipping nodes
+    /// on hidden layers, in no particular order.
+    ///
+    /// The area is the one of the last frame drawn by [`Map::show`]; before
+    /// the first frame nothing is visible.
+    pub fn visible_nodes(&self) -> Vec<usize> {
+        let _span = tracing::info_span!("visible_nodes").entered();
+        if self.map_area.area() <= 0.0 {
+            return Vec::new();
+        }
+        let area = self.visible_map_rect();
+        self.nodes_in_bounds(area.min.into(), area.max.into())
+    }
+
+    /// Returns the ids of the selected nodes.
+    ///
+    /// The selection is independent of the hovered node and of
+    /// [`MapSettings::node_text_visibility`]: it changes when the user clicks
+    /// on nodes (see [`MapSettings::selection_mode`]) or through the selection
+    /// methods of the widget, and is highlighted by the default renderer or by
+    /// [`NodeTemplate::selected_ui`].
+    pub fn selected_nodes(&self) -> &HashSet<usize> {
+        &self.selection
+    }
+
+    /// Returns `true` if the node `node_id` is selected.
+    pub fn is_node_selected(&self, node_id: usize) -> bool {
+        self.selection.contains(&node_id)
+    }
+
+    /// Adds the node `node_id` to the selection.
+    pub fn select_node(&mut self, node_id: usize) {
+        self.selection.insert(node_id);
+    }
+
+    /// Removes the node `node_id` from the selection.
+    pub fn deselect_node(&mut self, node_id: usize) {
+        self.selection.remove(&node_id);
+    }
+
+    /// Selects the node `node_id` if it was not selected, and deselects it
+    /// otherwise.
+    pub fn toggle_node_selection(&mut self, node_id: usize) {
+        if !self.selection.remove(&node_id) {
+            self.selection.insert(node_id);
+        }
+    }
+
+    /// Replaces the selection with the given node ids.
+    pub fn set_selection(&mut self, node_ids: impl IntoIterator<Item = usize>) {
+        self.selection = node_ids.into_iter().collect();
+    }
+
+    /// Deselects every node.
+    pub fn clear_selection(&mut self) {
+        self.selection.clear();
+    }
+
+    /// Installs a right-click context menu whose contents are built by the
+    /// given [`ContextMenuManager`] implementation.
+    pub fn set_context_manager(&mut self, manager: Rc<dyn ContextMenuManager>) {
+        self.menu_manager = Some(manager);
+    }
+
+    /// Replaces the built-in node rendering with a custom [`NodeTemplate`]
+    /// implementation.
+    ///
+    /// The template takes over the drawing of nodes, selection highlights,
+    /// notification animations and markers — including the node name labels,
+    /// which the widget no longer draws once a template is installed. See the
+    /// [`NodeTemplate`] examples for custom shapes and animations.
+    pub fn set_node_template(&mut self, template: Rc<dyn NodeTemplate>) {
+        self.node_template = Some(template);
+    }
+
+    /// Replaces the default cluster bubble with a custom [`ClusterTemplate`].
+    ///
+    /// Only used while [`MapSettings::cluster_radius`] is set.
+    pub fn set_cluster_template(&mut self, template: Rc<dyn ClusterTemplate>) {
+        self.cluster_template = Some(template);
+    }
+
+    /// Returns the node clusters drawn in the last frame; empty unless
+    /// [`MapSettings::cluster_radius`] is set.
+    pub fn clusters(&self) -> &[NodeCluster] {
+        &self.clusters
+    }
+
+    /// Groups the shown nodes in the viewport that are closer than
+    /// [`MapSettings::cluster_radius`] screen points to each other.
+    ///
+    /// Nothing is clustered at [`MapSettings::max_zoom`], so zooming in on a
+    /// cluster always ends up showing its members.
+    fn update_clusters(&mut self) {
+        let _span = tracing::info_span!("update_clusters").entered();
+        self.clusters.clear();
+        self.aggregated.clear();
+        if let Some(view) = &self.group_view {
+            self.aggregated.extend(&view.hidden_nodes);
+        }
+        let radius = self.settings.cluster_radius / self.zoom;
+        if !(radius > 0.0 && radius.is_finite()) || self.zoom >= self.settings.max_zoom {
+            return;
+        }
+        let candidates: Vec<(usize, [f32; 2])> = self
+            .visible_points
+            .iter()
+            .filter_map(|id| self.point(id.cast_unsigned()))
+            .filter(|point| self.is_node_shown(point) && !self.aggregated.contains(&point.id))
+            .map(|point| (point.id, point.coords))
+            .collect();
+        for members in cluster_by_distance(&candidates, radius) {
+            let mut center = RawPoint::default();
+            for id in &members {
+                center = center + RawPoint::from(self.points.as_ref().unwrap()[id].coords);
+            }
+            self.aggregated.extend(&members);
+            self.clusters.push(NodeCluster {
+                center: (center / members.len() as f32).into(),
+                members,
+            });
+        }
+        self.clusters.sort_by_key(|cluster| cluster.members[0]);
+    }
+
+    fn paint_clusters(&self, paint: &Painter, ui_obj: &mut Ui, transform: &MapTransform) {
+        let _span = tracing::info_span!("paint_clusters").entered();
+        let style = self.current_style();
+        for cluster in &self.clusters {
+            let position = transform.map_to_screen(cluster.center);
+            if let Some(template) = &self.cluster_template {
+                template.cluster_ui(ui_obj, position, self.zoom, cluster);
+                continue;
+            }
+            let radius = cluster_glyph_radius(cluster.members.len());
+            paint.circle_filled(position, radius, style.fill_color);
+            if let Some(border) = style.border {
+                paint.circle_stroke(position, radius, Stroke::new(1.5, border.color));
+            }
+            paint.text(
+                position,
+                Align2::CENTER_CENTER,
+                cluster.members.len().to_string(),
+                FontId::proportional(radius),
+                style.background_color,
+            );
+        }
+    }
+
+    /// Registers a node group under `id`, replacing and returning any group
+    /// previously registered with that id.
+    ///
+    /// Nodes join the group by setting [`MapPoint::group`] to the same id,
+    /// and groups nest by setting [`NodeGroup::parent`]. While the zoom is
+    /// below [`NodeGroup::expand_zoom`] the group is drawn as a single shape
+    /// with its name, covering its nodes and subgroups, and the lines leading
+    /// into it are merged into one line per neighbouring node or group. These
+    /// merged lines are drawn at every zoom level. Clicking a collapsed group
+    /// zooms in on it and reports [`MapEvent::GroupClicked`].
+    pub fn add_node_group(&mut self, id: usize, group: NodeGroup) -> Option<NodeGroup> {
+        let _span = tracing::info_span!("add_node_group").entered();
+        self.group_view = None;
+        self.node_groups.insert(id, group)
+    }
+
+    /// Unregisters a node group and returns it. Its nodes and subgroups are
+    /// kept; they are no longer folded into it.
+    pub fn remove_node_group(&mut self, id: usize) -> Option<NodeGroup> {
+        let _span = tracing::info_span!("remove_node_group").entered();
+        self.group_view = None;
+        self.node_groups.remove(&id)
+    }
+
+    /// Returns the node group registered under `id`.
+    pub fn node_group(&self, id: usize) -> Option<&NodeGroup> {
+        self.node_groups.get(&id)
+    }
+
+    /// Unregisters every node group.
+    pub fn clear_node_groups(&mut self) {
+        self.group_view = None;
+        self.node_groups.clear();
+    }
+
+    /// Returns the ids of the groups drawn collapsed in the last frame,
+    /// outermost only and sorted. Groups without nodes are included even
+    /// though nothing is drawn for them.
+    pub fn collapsed_groups(&self) -> Vec<usize> {
+        self.group_view
+            .as_ref()
+            .map(|view| view.collapsed.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Drops the group view if the edit of `point` may change it, so it is
+    /// rebuilt on the next frame.
+    fn invalidate_group_view(&mut self, point: &MapPoint) {
+        if self
+            .group_view
+            .as_ref()
+            .is_some_and(|view| view.is_affected_by(point))
+        {
+            self.group_view = None;
+        }
+    }
+
+    /// Rebuilds the group view when a group crossed its expand threshold or
+    /// the nodes or groups changed since it was built.
+    fn update_group_view(&mut self) {
+        if self.node_groups.is_empty() {
+            self.group_view = None;
+            return;
+        }
+        let collapsed = collapsed_groups(&self.node_groups, self.zoom);
+        if self
+            .group_view
+            .as_ref()
+            .is_some_and(|view| view.collapsed == collapsed)
+        {
+            return;
+        }
+        let empty = HashMap::new();
+        let points = self.points.as_ref().unwrap_or(&empty);
+        self.group_view = Some(build_group_view(points, &self.node_groups, collapsed));
+    }
+
+    /// Radius of a collapsed group shape, in screen points.
+    fn group_glyph_radius(&self, shape: &GroupShape) -> f32 {
+        shape.extent * self.zoom + cluster_glyph_radius(shape.members)
+    }
+
+    fn paint_group_edges(&self, paint: &Painter, transform: &MapTransform) {
+        if let Some(view) = &self.group_view
+            && let Some(stroke) = self.current_style().line
+        {
+            let _span = tracing::info_span!("paint_group_edges").entered();
+            let visible = self.visible_map_rect().expand(stroke.width / self.zoom);
+            paint.extend(
+                view.edges
+                    .iter()
+                    .filter(|edge| {
+                        Rect::from_two_pos(edge[0].into(), edge[1].into()).intersects(visible)
+                    })
+                    .map(|edge| {
+                        Shape::line_segment(
+                            edge.map(|point| transform.map_to_screen(point)),
+                            stroke,
+                        )
+                    }),
+            );
+        }
+    }
+
+    fn paint_group_shapes(&self, paint: &Painter, ui_obj: &Ui, transform: &MapTransform) {
+        let Some(view) = &self.group_view else {
+            return;
+        };
+        let _span = tracing::info_span!("paint_group_shapes").entered();
+        let style = self.current_style();
+        let clip = paint.clip_rect();
+        for shape in &view.shapes {
+            let position = transform.map_to_screen(shape.center);
+            let radius = self.group_glyph_radius(shape);
+            if !clip.expand(radius).contains(position) {
+                continue;
+            }
+            paint.circle(
+                position,
+                radius,
+                style.fill_color.gamma_multiply(0.35),
+                Stroke::new(1.5, style.border.map_or(style.fill_color, |b| b.color)),
+            );
+            paint.text(
+                position,
+                Align2::CENTER_CENTER,
+                &self.node_groups[&shape.id].name,
+                FontId::proportional(14.0),
+                ui_obj.visuals().text_color(),
+            );
+        }
+    }
+
+    /// Highlights a route through the given nodes, replacing the route
+    /// previously registered with the same `id`.
+    ///
+    /// Consecutive nodes are joined along the line loaded between them (a
+    /// connection listed by both nodes, or the line keyed by their two ids),
+    /// drawn again above the regular lines; nodes with no line between them
+    /// are joined by a straight line from one to the other. The nodes are
+    /// numbered in order (see [`RouteStyle::numbered`]). Routes are drawn at
+    /// every zoom level, even below [`MapSettings::line_visible_zoom`].
+    /// Unknown node ids are skipped together with the lines reaching them.
+    /// Pass [`Route::nodes`] to show a route computed with
+    /// [`Map::shortest_path`].
+    pub fn set_route(&mut self, id: usize, nodes: Vec<usize>, style: RouteStyle) {
+        let _span = tracing::info_span!("set_route").entered();
+        self.routes.insert(id, MapRoute { nodes, style });
+    }
+
+    /// Removes the route with the given id. Returns `false` if there was no
+    /// such route.
+    pub fn remove_route(&mut self, id: usize) -> bool {
+        self.routes.remove(&id).is_some()
+    }
+
+    /// Removes every route.
+    pub fn clear_routes(&mut self) {
+        self.routes.clear();
+    }
+
+    /// Registers a custom overlay painted at `z_index` and returns an id to
+    /// remove it later with [`Map::remove_map_layer`].
+    ///
+    /// Use the built-in z-indexes ([`Map::LABELS_Z`], [`Map::LINES_Z`],
+    /// [`Map::NODES_Z`] and [`Map::MARKERS_Z`]) to place the layer, e.g.
+    /// `Map::NODES_Z - 1` to draw between lines and nodes. Layers with the
+    /// same z-index are painted in the order they were added.
+    pub fn add_map_layer(&mut self, z_index: i32, layer: Rc<dyn MapLayer>) -> usize {
+        let _span = tracing::info_span!("add_map_layer").entered();
+        let id = self.next_map_layer_id;
+        self.next_map_layer_id += 1;
+        let index = self
+            .map_layers
+            .partition_point(|entry| entry.z_index <= z_index);
+        self.map_layers
+            .insert(index, MapLayerEntry { id, z_index, layer });
+        id
+    }
+
+    /// Unregisters the custom overlay with the given id and returns it.
+    pub fn remove_map_layer(&mut self, id: usize) -> Option<Rc<dyn MapLayer>> {
+        let _span = tracing::info_span!("remove_map_layer").entered();
+        let index = self.map_layers.iter().position(|entry| entry.id == id)?;
+        Some(self.map_layers.remove(index).layer)
+    }
+
+    /// Unregisters every custom overlay.
+    pub fn clear_map_layers(&mut self) {
+        self.map_layers.clear();
+    }
+
+    /// Draws an image under everything else on the map, stretched over the
+    /// rectangle with corners `a` and `b` in map coordinates, and returns an
+    /// id to remove it later with [`Map::remove_image_overlay`].
+    ///
+    /// The image pans and zooms with the nodes, so it suits backdrops and
+    /// floor plans. `image` is anything egui can draw: a loaded texture
+    /// (`&TextureHandle`, [`load::SizedTexture`]), encoded bytes as a
+    /// `("bytes://name.png", bytes)` pair, or a URI. Bytes and URIs are
+    /// decoded by the image loaders installed in the [`Context`], e.g. with
+    /// `egui_extras::install_image_loaders`; nothing is drawn until they
+    /// finish, and a warning is logged if no loader can decode the image.
+    /// `opacity` goes from `0.0` (invisible) to `1.0`. Overlays are painted
+    /// in the order they were added.
+    pub fn add_image_overlay(
+        &mut self,
+        image: impl Into<ImageSource<'static>>,
+        a: [f32; 2],
+        b: [f32; 2],
+        opacity: f32,
+    ) -> usize {
+        let _span = tracing::info_span!("add_image_overlay").entered();
+        let id = self.next_image_overlay_id;
+        self.next_image_overlay_id += 1;
+        let (min, max) = rect_corners(a, b);
+        self.image_overlays.push(ImageOverlay {
+            id,
+            source: image.into(),
+            rect: Rect::from_min_max(min.into(), max.into()),
+            opacity: opacity.clamp(0.0, 1.0),
+            load_failed: std::cell::Cell::new(false),
+        });
+        id
+    }
+
+    /// Changes the opacity of the image overlay with the given id. Returns
+    /// `false` if there is no such overlay.
+    pub fn set_image_overlay_opacity(&mut self, id: usize, opacity: f32) -> bool {
+        self.image_overlays
+            .iter_mut()
+            .find(|overlay| overlay.id == id)
+            .map(|overlay| overlay.opacity = opacity.clamp(0.0, 1.0))
+            .is_some()
+    }
+
+    /// Removes the image overlay with the given id. Returns `false` if there
+    /// was no such overlay.
+    pub fn remove_image_overlay(&mut self, id: usize) -> bool {
+        let _span = tracing::info_span!("remove_image_overlay").entered();
+        let before = self.image_overlays.len();
+        self.image_overlays.retain(|overlay| overlay.id != id);
+        self.image_overlays.len() != before
+    }
+
+    /// Removes every image overlay.
+    pub fn clear_image_overlays(&mut self) {
+        self.image_overlays.clear();
+    }
+
+    fn paint_image_overlays(&self, paint: &Painter, transform: &MapTransform) {
+        let _span = tracing::info_span!("paint_image_overlays").entered();
+        let visible = self.visible_map_rect();
+        for overlay in &self.image_overlays {
+            if overlay.opacity <= 0.0 || !overlay.rect.intersects(visible) {
+                continue;
+            }
+            // loaders that are still decoding ask for a repaint when done
+            let poll = overlay.source.clone().load(
+                paint.ctx(),
+                TextureOptions::LINEAR,
+                load::SizeHint::default(),
+            );
+            if let Err(error) = &poll
+                && !overlay.load_failed.replace(true)
+            {
+                tracing::warn!(
+                    "image overlay {} ({:?}) could not be loaded: {error}",
+                    overlay.id,
+                    overlay.source
+                );
+            }
+            if let Ok(load::TexturePoll::Ready { texture }) = poll {
+                paint.image(
+                    texture.id,
+                    Rect::from_min_max(
+                        transform.map_to_screen(overlay.rect.min.into()),
+                        transform.map_to_screen(overlay.rect.max.into()),
+                    ),
+                    Rect::from_min_max(Pos2::ZERO, pos2(1.0, 1.0)),
+                    Color32::WHITE.gamma_multiply(overlay.opacity),
+                );
+            }
+        }
+    }
+
+    /// Registers a node layer under `name`, replacing and returning any layer
+    /// previously registered with that name.
+    ///
+    /// Nodes join the layer by setting [`MapPoint::layer`] to the same name,
+    /// which may happen before or after the layer is registered.
+    pub fn add_node_layer(
+        &mut self,
+        name: impl Into<String>,
+        layer: NodeLayer,
+    ) -> Option<NodeLayer> {
+        let _span = tracing::info_span!("add_node_layer").entered();
+        self.node_layers.insert(name.into(), layer)
+    }
+
+    /// Unregisters a node layer and returns it. Its nodes are kept, and drawn
+    /// as part of the default layer from then on.
+    pub fn remove_node_layer(&mut self, name: &str) -> Option<NodeLayer> {
+        let _span = tracing::info_span!("remove_node_layer").entered();
+        self.node_layers.remove(name)
+    }
+
+    /// Returns the node layer registered under `name`.
+    pub fn node_layer(&self, name: &str) -> Option<&NodeLayer> {
+        self.node_layers.get(name)
+    }
+
+    /// Returns the node layer registered under `name` for editing.
+    pub fn node_layer_mut(&mut self, name: &str) -> Option<&mut NodeLayer> {
+        self.node_layers.get_mut(name)
+    }
+
+    /// Shows or hides the node layer registered under `name`. Returns `false`
+    /// if there is no such layer.
+    pub fn set_node_layer_visible(&mut self, name: &str, visible: bool) -> bool {
+        let _span = tracing::info_span!("set_node_layer_visible").entered();
+        self.node_layers
+            .get_mut(name)
+            .map(|layer| layer.visible = visible)
+            .is_some()
+    }
+
+    /// Adds the marker `id`, or moves it, so it points to the node `node_id`.
+    ///
+    /// Markers are drawn as a blinking ring around the target node unless a
+    /// custom [`objects::NodeTemplate::marker_ui`] is installed.
+    pub fn update_marker(&mut self, id: usize, node_id: usize) {
+        self.markers
+            .entry(id)
+            .and_modify(|value| *value = node_id)
+            .or_insert(node_id);
+    }
+
+    /// Sets the minimum width and/or height the widget should occupy, in egui
+    /// points. `None` leaves the corresponding dimension unconstrained.
     pub fn allocate_at_least(&mut self, width: Option<f32>, height: Option<f32>) {
         self.min_size = (width, height);
     }
@@ -991,17 +3139,116 @@ impl Map {
     }
 }
 
+/// Radius of the default cluster bubble, in screen points.
+fn cluster_glyph_radius(members: usize) -> f32 {
+    10.0 + 3.0 * (members as f32).log2()
+}
+
+/// Splits `points` into the groups of two or more that are linked by
+/// distances below `radius`, each listing its ids in ascending order.
+///
+/// Points are bucketed in a grid of `radius`-sized cells, so only the eight
+/// cells around each point need to be searched for neighbours.
+fn cluster_by_distance(points: &[(usize, [f32; 2])], radius: f32) -> Vec<Vec<usize>> {
+    fn root(parent: &mut [usize], mut index: usize) -> usize {
+        while parent[index] != index {
+            parent[index] = parent[parent[index]];
+            index = parent[index];
+        }
+        index
+    }
+    let cell_of = |coords: [f32; 2]| coords.map(|value| (value / radius).floor() as i64);
+    let mut cells: HashMap<[i64; 2], Vec<usize>> = HashMap::new();
+    for (index, (_, coords)) in points.iter().enumerate() {
+        cells.entry(cell_of(*coords)).or_default().push(index);
+    }
+    // union-find over indexes into `points`
+    let mut parent: Vec<usize> = (0..points.len()).collect();
+    for (index, (_, coords)) in points.iter().enumerate() {
+        let [x, y] = cell_of(*coords);
+        for key in (x - 1..=x + 1).flat_map(|x| (y - 1..=y + 1).map(move |y| [x, y])) {
+            for &other in cells.get(&key).into_iter().flatten() {
+                let [ox, oy] = points[other].1;
+                if other > index && (coords[0] - ox).hypot(coords[1] - oy) < radius {
+                    let (a, b) = (root(&mut parent, index), root(&mut parent, other));
+                    parent[a] = b;
+                }
+            }
+        }
+    }
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (index, (id, _)) in points.iter().enumerate() {
+        let group = root(&mut parent, index);
+        groups.entry(group).or_default().push(*id);
+    }
+    groups
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|mut members| {
+            members.sort_unstable();
+            members
+        })
+        .collect()
+}
+
+/// Orders the corners of a rectangle given by two opposite corners.
+fn rect_corners(a: [f32; 2], b: [f32; 2]) -> ([f32; 2], [f32; 2]) {
+    (
+        [a[0].min(b[0]), a[1].min(b[1])],
+        [a[0].max(b[0]), a[1].max(b[1])],
+    )
+}
+
+/// Tests whether `line` touches the axis-aligned box from `min` to `max`, by
+/// clipping the segment against each pair of box edges.
+fn segment_hits_rect(line: RawLine, min: [f32; 2], max: [f32; 2]) -> bool {
+    let [start, end] = line.points.map(|point| point.components);
+    let (mut enter, mut exit) = (0.0_f32, 1.0_f32);
+    for axis in 0..2 {
+        let delta = end[axis] - start[axis];
+        if delta == 0.0 {
+            if start[axis] < min[axis] || start[axis] > max[axis] {
+                return false;
+            }
+            continue;
+        }
+        let t1 = (min[axis] - start[axis]) / delta;
+        let t2 = (max[axis] - start[axis]) / delta;
+        enter = enter.max(t1.min(t2));
+        exit = exit.min(t1.max(t2));
+    }
+    enter <= exit
+}
+
+/// Even-odd test of `point` against the closed polygon `polygon`.
+fn polygon_contains(polygon: &[RawPoint], point: RawPoint) -> bool {
+    let [x, y] = point.components;
+    let mut inside = false;
+    let mut previous = match polygon.last() {
+        Some(last) => last.components,
+        None => return false,
+    };
+    for vertex in polygon {
+        let [xi, yi] = vertex.components;
+        let [xj, yj] = previous;
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        previous = vertex.components;
+    }
+    inside
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::Duration;
 
     fn sample_points() -> Vec<MapPoint> {
-        let mut map = Vec::new();
-        map.push(MapPoint::new(1, [0.0, 0.0]));
-        map.push(MapPoint::new(2, [10.0, 10.0]));
-        map.push(MapPoint::new(3, [-10.0, -10.0]));
-        map
+        vec![
+            MapPoint::new(1, [0.0, 0.0]),
+            MapPoint::new(2, [10.0, 10.0]),
+            MapPoint::new(3, [-10.0, -10.0]),
+        ]
     }
 
     // ---------- construcción ----------
@@ -1049,218 +3296,1726 @@ mod tests {
     }
 
     #[test]
-    fn set_zoom_out_of_range_is_ignored() {
+    fn set_zoom_out_of_range_is_ignored() {
+        let mut map = Map::new();
+        let initial = map.get_zoom();
+        map.set_zoom(0.05); // por debajo de min_zoom
+        assert_eq!(map.get_zoom(), initial);
+        map.set_zoom(2.5); // por encima de max_zoom
+        assert_eq!(map.get_zoom(), initial);
+    }
+
+    #[test]
+    fn set_zoom_at_keeps_anchor_fixed() {
+        let mut map = Map::new();
+        map.map_area = Rect::from_min_size(Pos2::ZERO, vec2(500.0, 500.0));
+        map.set_pos([0.0, 0.0]);
+
+        // el punto (100,0) del mapa está en (350,250) con zoom 1
+        map.set_zoom_at(2.0, Pos2::new(350.0, 250.0));
+        assert_eq!(map.get_zoom(), 2.0);
+        assert_eq!(map.get_pos(), [50.0, 0.0]);
+        assert_eq!(
+            map.screen_to_map_point(Pos2::new(350.0, 250.0)).components,
+            [100.0, 0.0]
+        );
+
+        // fuera de rango se recorta en vez de ignorarse
+        map.set_zoom_at(99.0, Pos2::new(350.0, 250.0));
+        assert_eq!(map.get_zoom(), map.settings.max_zoom);
+    }
+
+    #[test]
+    fn mouse_wheel_zooms_around_pointer() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.set_pos([0.0, 0.0]);
+        run_frame(&ctx, &mut map, Vec::new());
+
+        // el nodo 2 (10,10) está en (260,260)
+        let pointer = Pos2::new(260.0, 260.0);
+        let wheel = egui::Event::MouseWheel {
+            unit: egui::MouseWheelUnit::Point,
+            delta: vec2(0.0, 20.0),
+            modifiers: egui::Modifiers::NONE,
+            phase: egui::TouchPhase::Move,
+        };
+        run_frame(
+            &ctx,
+            &mut map,
+            vec![egui::Event::PointerMoved(pointer), wheel],
+        );
+        assert!(map.get_zoom() > 1.0);
+        let under_pointer = map.screen_to_map_point(pointer).components;
+        assert!((under_pointer[0] - 10.0).abs() < 1e-3);
+        assert!((under_pointer[1] - 10.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn trackpad_pinch_zooms_around_pointer() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.set_pos([0.0, 0.0]);
+        run_frame(&ctx, &mut map, Vec::new());
+
+        let pointer = Pos2::new(260.0, 260.0);
+        run_frame(
+            &ctx,
+            &mut map,
+            vec![egui::Event::PointerMoved(pointer), egui::Event::Zoom(1.5)],
+        );
+        assert!((map.get_zoom() - 1.5).abs() < 1e-3);
+        let under_pointer = map.screen_to_map_point(pointer).components;
+        assert!((under_pointer[0] - 10.0).abs() < 1e-3);
+        assert!((under_pointer[1] - 10.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn scroll_action_pan_moves_view_and_command_zooms() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.settings.scroll_action = ScrollAction::Pan;
+        map.add_points(sample_points());
+        map.set_pos([0.0, 0.0]);
+        run_frame(&ctx, &mut map, Vec::new());
+
+        let pointer = Pos2::new(260.0, 260.0);
+        let wheel = |modifiers| egui::Event::MouseWheel {
+            unit: egui::MouseWheelUnit::Point,
+            delta: vec2(0.0, 20.0),
+            modifiers,
+            phase: egui::TouchPhase::Move,
+        };
+        run_frame(
+            &ctx,
+            &mut map,
+            vec![
+                egui::Event::PointerMoved(pointer),
+                wheel(egui::Modifiers::NONE),
+            ],
+        );
+        // el contenido sigue a la rueda: la vista sube
+        assert_eq!(map.get_zoom(), 1.0);
+        assert_eq!(map.get_pos(), [0.0, -20.0]);
+
+        run_frame(&ctx, &mut map, vec![wheel(egui::Modifiers::COMMAND)]);
+        assert!(map.get_zoom() > 1.0);
+    }
+
+    // ---------- fly_to ----------
+
+    #[test]
+    fn fly_to_reaches_destination_after_duration() {
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.set_pos([0.0, 0.0]);
+        map.fly_to(
+            [10.0, -10.0],
+            2.0,
+            Duration::from_secs(1),
+            Easing::EaseInOut,
+        );
+        assert!(map.is_flying());
+        // todavía no se ha movido
+        assert_eq!(map.get_pos(), [0.0, 0.0]);
+
+        let start = Instant::now();
+        assert!(map.advance_flight(start + Duration::from_millis(500)));
+        let [x, y] = map.get_pos();
+        assert!(x > 0.0 && x < 10.0);
+        assert!(y < 0.0 && y > -10.0);
+        assert!(map.get_zoom() > 1.0 && map.get_zoom() < 2.0);
+
+        assert!(!map.advance_flight(start + Duration::from_secs(2)));
+        assert!(!map.is_flying());
+        assert_eq!(map.get_pos(), [10.0, -10.0]);
+        assert_eq!(map.get_zoom(), 2.0);
+    }
+
+    #[test]
+    fn fly_to_node_with_zero_duration_jumps() {
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.fly_to_node(2, Duration::ZERO, Easing::Linear);
+        assert!(!map.is_flying());
+        assert_eq!(map.get_pos(), [10.0, 10.0]);
+
+        // un id desconocido no inicia ningún vuelo
+        map.fly_to_node(99, Duration::from_secs(1), Easing::Linear);
+        assert!(!map.is_flying());
+    }
+
+    #[test]
+    fn fly_to_is_cancelled_by_jumps_and_user_input() {
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.fly_to([10.0, 10.0], 1.0, Duration::from_secs(5), Easing::Linear);
+        map.set_pos([-10.0, -10.0]);
+        assert!(!map.is_flying());
+        map.fly_to([10.0, 10.0], 1.0, Duration::from_secs(5), Easing::Linear);
+        map.set_zoom_at(2.0, Pos2::new(100.0, 100.0));
+        assert!(!map.is_flying());
+
+        let ctx = egui::Context::default();
+        map.set_pos([0.0, 0.0]);
+        run_frame(&ctx, &mut map, Vec::new());
+        map.fly_to([10.0, 10.0], 1.0, Duration::from_secs(5), Easing::Linear);
+        let wheel = egui::Event::MouseWheel {
+            unit: egui::MouseWheelUnit::Point,
+            delta: vec2(0.0, 20.0),
+            modifiers: egui::Modifiers::NONE,
+            phase: egui::TouchPhase::Move,
+        };
+        run_frame(
+            &ctx,
+            &mut map,
+            vec![egui::Event::PointerMoved(Pos2::new(250.0, 250.0)), wheel],
+        );
+        assert!(!map.is_flying());
+    }
+
+    // ---------- fit ----------
+
+    #[test]
+    fn fit_bounds_centers_and_fills_widget() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        run_frame(&ctx, &mut map, Vec::new());
+
+        map.fit_bounds([-1000.0, -500.0], [3000.0, 1500.0]);
+        let size = map.map_area.size();
+        let expected = (size.x / 4000.0).min(size.y / 2000.0);
+        assert_eq!(map.get_pos(), [1000.0, 500.0]);
+        assert!((map.get_zoom() - expected).abs() < 1e-6);
+
+        // un rectángulo diminuto queda limitado por max_zoom
+        map.fit_bounds([0.0, 0.0], [1.0, 1.0]);
+        assert_eq!(map.get_zoom(), map.settings.max_zoom);
+    }
+
+    #[test]
+    fn fit_nodes_applies_padding_and_ignores_unknown_ids() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.settings.max_zoom = 100.0;
+        map.add_points(sample_points());
+        run_frame(&ctx, &mut map, Vec::new());
+
+        map.fit_nodes(&[1, 2, 99], 50.0);
+        let size = map.map_area.size() - vec2(100.0, 100.0);
+        assert_eq!(map.get_pos(), [5.0, 5.0]);
+        assert!((map.get_zoom() - size.x.min(size.y) / 10.0).abs() < 1e-4);
+
+        // un solo nodo se centra sin cambiar el zoom
+        let zoom = map.get_zoom();
+        map.fit_nodes(&[3], 50.0);
+        assert_eq!(map.get_pos(), [-10.0, -10.0]);
+        assert_eq!(map.get_zoom(), zoom);
+    }
+
+    #[test]
+    fn fit_all_before_first_frame_is_applied_on_render() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.settings.max_zoom = 100.0;
+        map.add_points(sample_points());
+        map.set_pos([50.0, 50.0]);
+        map.fit_all();
+        // sin área todavía no se aplica
+        assert_eq!(map.get_pos(), [50.0, 50.0]);
+
+        run_frame(&ctx, &mut map, Vec::new());
+        let size = map.map_area.size();
+        assert_eq!(map.get_pos(), [0.0, 0.0]);
+        assert!((map.get_zoom() - size.x.min(size.y) / 20.0).abs() < 1e-4);
+    }
+
+    // ---------- puntos ----------
+
+    #[test]
+    fn add_hashmap_points_computes_bounds() {
+        let mut map = Map::new();
+        map.add_points(sample_points());
+
+        assert_eq!(map.reference.min.components, [-10.0, -10.0]);
+        assert_eq!(map.reference.max.components, [10.0, 10.0]);
+        // pos es el punto medio del rectángulo que contiene todos los puntos
+        assert_eq!(map.reference.pos.components, [0.0, 0.0]);
+        // map_area tiene área 0 antes de renderizar, así que dist es el valor fijo
+        assert_eq!(map.reference.dist, 3000.0);
+        // current se inicializa como copia de reference
+        assert_eq!(map.current.min.components, map.reference.min.components);
+        assert_eq!(map.current.max.components, map.reference.max.components);
+        assert_eq!(map.current.pos.components, map.reference.pos.components);
+        assert_eq!(map.current.dist, map.reference.dist);
+        assert!(map.points.is_some());
+        assert!(map.tree.is_some());
+        assert_eq!(map.points.as_ref().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn add_hashmap_points_populates_visible_points() {
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        // todos los puntos de muestra caen dentro del radio por defecto
+        assert_eq!(map.visible_points.len(), 3);
+    }
+
+    /// Renders one frame of `map` in a 500x500 viewport and returns the
+    /// painted line segments.
+    fn render_line_segments(map: &mut Map) -> Vec<[egui::Pos2; 2]> {
+        use egui::{Context, RawInput, Shape};
+        let ctx = Context::default();
+        let input = RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(500.0, 500.0),
+            )),
+            ..RawInput::default()
+        };
+        let mut output = ctx.run_ui(input, |ui| {
+            ui.add(&mut *map);
+        });
+        // Headless run: no renderer consumes the font atlas upload.
+        output.textures_delta.clear();
+        output
+            .shapes
+            .iter()
+            .filter_map(|cs| match cs.shape {
+                Shape::LineSegment { points, .. } => Some(points),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn segment_crossing_viewport_is_painted_even_with_far_endpoints() {
+        // With the old endpoint-based rule this line was culled: both
+        // endpoints sit beyond the point-culling radius. With the R-tree the
+        // segment AABB intersects the viewport, so it is painted — no points
+        // needed at all.
+        let mut map = Map::new();
+        map.set_zoom(1.0);
+        let lines = vec![MapSegment::new((1, 2), [-4000.0, -1.0], [4000.0, 1.0])];
+        map.add_lines(lines);
+        map.set_pos([0.0, 0.0]);
+
+        let segments = render_line_segments(&mut map);
+        assert_eq!(segments.len(), 1);
+    }
+
+    #[test]
+    fn segment_outside_viewport_is_not_painted() {
+        let mut map = Map::new();
+        map.set_zoom(1.0);
+        let lines = vec![MapSegment::new(
+            (1, 2),
+            [10_000.0, 10_000.0],
+            [10_100.0, 10_100.0],
+        )];
+        map.add_lines(lines);
+        map.set_pos([0.0, 0.0]);
+
+        assert!(render_line_segments(&mut map).is_empty());
+    }
+
+    #[test]
+    fn add_lines_builds_segment_tree() {
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        let lines = vec![MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0])];
+        map.add_lines(lines);
+
+        let tree = map
+            .segments
+            .as_ref()
+            .expect("add_lines must build the segment tree");
+        assert_eq!(tree.size(), 1);
+
+        // Broad-phase query: a viewport containing (0,0) must hit the segment;
+        // a far-away viewport must not.
+        let hit_query = rstar::AABB::from_corners([-1.0, -1.0], [1.0, 1.0]);
+        let hits: Vec<_> = tree.locate_in_envelope_intersecting(hit_query).collect();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, (1, 2));
+
+        let miss_query = rstar::AABB::from_corners([100.0, 100.0], [200.0, 200.0]);
+        assert_eq!(tree.locate_in_envelope_intersecting(miss_query).count(), 0);
+    }
+
+    #[test]
+    fn map_check_line_is_painted_on_first_frame() {
+        use egui::{Context, RawInput, Shape};
+
+        // --- arrange ---
+        let mut map = Map::new();
+        map.set_zoom(1.0);
+
+        let mut point_a = MapPoint::new(0, [0.0, 0.0]);
+        point_a.connections.push((0, 1));
+        let mut point_b = MapPoint::new(1, [50.0, 50.0]);
+        point_b.connections.push((0, 1));
+
+        let lines = vec![MapSegment::new((0, 1), point_a.coords, point_b.coords)];
+
+        let points = vec![point_a, point_b];
+        // Load points before lines — the natural order shown in the examples.
+        map.add_points(points);
+        map.add_lines(lines);
+
+        map.set_pos([25.0, 25.0]);
+
+        // --- act: 1st frame (no CentralPanel — run_ui creates the root Ui) ---
+        let ctx = Context::default();
+        let screen = egui::Rect::from_min_size(egui::Pos2::ZERO, egui::vec2(500.0, 500.0));
+        let input = RawInput {
+            screen_rect: Some(screen),
+            ..RawInput::default()
+        };
+
+        let mut output1 = ctx.run_ui(input.clone(), |ui| {
+            ui.add(&mut map);
+        });
+        output1.textures_delta.clear();
+
+        let segments1: Vec<[egui::Pos2; 2]> = output1
+            .shapes
+            .iter()
+            .filter_map(|cs| match cs.shape {
+                Shape::LineSegment { points, .. } => Some(points),
+                _ => None,
+            })
+            .collect();
+
+        assert!(
+            !segments1.is_empty(),
+            "Frame 1: no LineSegment shapes painted (map lines did not draw)"
+        );
+
+        // Expected projection of (0,0)->(50,50) with zoom=1, center=(25,25),
+        // viewport 500x500: pos_a = (225, 225), pos_b = (275, 275). Tolerance ±2 px.
+        let expected_a = egui::pos2(225.0, 225.0);
+        let expected_b = egui::pos2(275.0, 275.0);
+        let tolerance = 2.0;
+        let found_on_frame1 = segments1.iter().any(|[p1, p2]| {
+            let d_a1 = p1.distance(expected_a);
+            let d_b1 = p2.distance(expected_b);
+            let d_a2 = p2.distance(expected_a);
+            let d_b2 = p1.distance(expected_b);
+            (d_a1 < tolerance && d_b1 < tolerance) || (d_a2 < tolerance && d_b2 < tolerance)
+        });
+        assert!(
+            found_on_frame1,
+            "Frame 1: no LineSegment matches expected endpoints (~225,225 -> ~275,275); got {:?}",
+            segments1
+        );
+
+        // --- act: 2nd frame (unchanged) — detect duplicate-lines regression ---
+        let mut output2 = ctx.run_ui(input, |ui| {
+            ui.add(&mut map);
+        });
+        output2.textures_delta.clear();
+
+        let segments2: Vec<[egui::Pos2; 2]> = output2
+            .shapes
+            .iter()
+            .filter_map(|cs| match cs.shape {
+                Shape::LineSegment { points, .. } => Some(points),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(
+            segments1.len(),
+            segments2.len(),
+            "Frame 2: expected {} line segments (no duplication across frames), got {}",
+            segments1.len(),
+            segments2.len()
+        );
+    }
+
+    // ---------- eventos ----------
+
+    /// Runs one 500x500 frame of `map` feeding it `events`, and returns the
+    /// widget response.
+    fn run_frame(ctx: &egui::Context, map: &mut Map, events: Vec<egui::Event>) -> MapResponse {
+        let input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(500.0, 500.0),
+            )),
+            events,
+            ..egui::RawInput::default()
+        };
+        let mut response = None;
+        let mut output = ctx.run_ui(input, |ui| {
+            response = Some(map.show(ui));
+        });
+        output.textures_delta.clear();
+        response.unwrap()
+    }
+
+    /// Moves the pointer to `pos` and clicks there, returning the events of
+    /// every frame.
+    fn click_at(ctx: &egui::Context, map: &mut Map, pos: Pos2) -> Vec<MapEvent> {
+        click_with_modifiers(ctx, map, pos, egui::Modifiers::NONE)
+    }
+
+    fn click_with_modifiers(
+        ctx: &egui::Context,
+        map: &mut Map,
+        pos: Pos2,
+        modifiers: egui::Modifiers,
+    ) -> Vec<MapEvent> {
+        let button = |pressed| egui::Event::PointerButton {
+            pos,
+            button: egui::PointerButton::Primary,
+            pressed,
+            modifiers,
+        };
+        let mut events = Vec::new();
+        for frame_events in [
+            vec![
+                egui::Event::ModifiersChanged(modifiers),
+                egui::Event::PointerMoved(pos),
+            ],
+            vec![button(true)],
+            vec![button(false)],
+        ] {
+            events.extend(run_frame(ctx, map, frame_events).events);
+        }
+        events
+    }
+
+    #[test]
+    fn show_reports_node_click_and_hover() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.set_pos([0.0, 0.0]);
+        run_frame(&ctx, &mut map, Vec::new());
+
+        // El nodo 1 (0,0) queda en el centro del viewport 500x500.
+        let events = click_at(&ctx, &mut map, Pos2::new(251.0, 249.0));
+        assert_eq!(
+            events,
+            vec![
+                MapEvent::NodeHoverEntered { id: 1 },
+                MapEvent::NodeClicked { id: 1 },
+                MapEvent::SelectionChanged,
+            ]
+        );
+
+        let events = run_frame(&ctx, &mut map, vec![egui::Event::PointerGone]).events;
+        assert_eq!(events, vec![MapEvent::NodeHoverLeft { id: 1 }]);
+    }
+
+    #[test]
+    fn show_reports_segment_and_background_clicks() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.add_lines(vec![MapSegment::new((1, 2), [0.0, 0.0], [100.0, 0.0])]);
+        map.set_pos([0.0, 0.0]);
+        run_frame(&ctx, &mut map, Vec::new());
+
+        let events = click_at(&ctx, &mut map, Pos2::new(300.0, 251.0));
+        assert_eq!(events, vec![MapEvent::SegmentClicked { id: (1, 2) }]);
+
+        let events = click_at(&ctx, &mut map, Pos2::new(200.0, 150.0));
+        assert_eq!(
+            events,
+            vec![MapEvent::BackgroundClicked {
+                position: [-50.0, -100.0]
+            }]
+        );
+    }
+
+    // ---------- selección ----------
+
+    #[test]
+    fn click_selects_and_modifier_click_toggles() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.set_pos([0.0, 0.0]);
+        run_frame(&ctx, &mut map, Vec::new());
+
+        // nodo 1 en (250,250), nodo 2 en (260,260)
+        click_at(&ctx, &mut map, Pos2::new(250.0, 250.0));
+        assert_eq!(map.selected_nodes(), &HashSet::from([1]));
+
+        let events = click_with_modifiers(
+            &ctx,
+            &mut map,
+            Pos2::new(260.0, 260.0),
+            egui::Modifiers::CTRL,
+        );
+        assert!(events.contains(&MapEvent::SelectionChanged));
+        assert_eq!(map.selected_nodes(), &HashSet::from([1, 2]));
+
+        click_with_modifiers(
+            &ctx,
+            &mut map,
+            Pos2::new(250.0, 250.0),
+            egui::Modifiers::SHIFT,
+        );
+        assert_eq!(map.selected_nodes(), &HashSet::from([2]));
+
+        // un clic sobre el fondo limpia la selección
+        let events = click_at(&ctx, &mut map, Pos2::new(100.0, 400.0));
+        assert!(events.contains(&MapEvent::SelectionChanged));
+        assert!(map.selected_nodes().is_empty());
+    }
+
+    #[test]
+    fn single_and_disabled_selection_modes() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.set_pos([0.0, 0.0]);
+        run_frame(&ctx, &mut map, Vec::new());
+
+        map.settings.selection_mode = SelectionMode::Single;
+        click_at(&ctx, &mut map, Pos2::new(250.0, 250.0));
+        click_with_modifiers(
+            &ctx,
+            &mut map,
+            Pos2::new(260.0, 260.0),
+            egui::Modifiers::CTRL,
+        );
+        assert_eq!(map.selected_nodes(), &HashSet::from([2]));
+
+        map.settings.selection_mode = SelectionMode::Disabled;
+        let events = click_at(&ctx, &mut map, Pos2::new(250.0, 250.0));
+        assert!(!events.contains(&MapEvent::SelectionChanged));
+        assert_eq!(map.selected_nodes(), &HashSet::from([2]));
+    }
+
+    #[test]
+    fn selection_api_and_reload_pruning() {
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.select_node(1);
+        map.select_node(3);
+        map.toggle_node_selection(3);
+        map.toggle_node_selection(2);
+        assert!(map.is_node_selected(1));
+        assert!(map.is_node_selected(2));
+        assert!(!map.is_node_selected(3));
+
+        map.deselect_node(1);
+        assert_eq!(map.selected_nodes(), &HashSet::from([2]));
+
+        // recargar los puntos descarta los nodos que ya no existen
+        map.set_selection([1, 2]);
+        map.add_points(vec![MapPoint::new(1, [0.0, 0.0])]);
+        assert_eq!(map.selected_nodes(), &HashSet::from([1]));
+
+        map.clear_selection();
+        assert!(map.selected_nodes().is_empty());
+    }
+
+    /// Presses at `path[0]`, moves through the rest of `path` and releases,
+    /// one frame per step, returning the events of every frame.
+    fn drag_along(
+        ctx: &egui::Context,
+        map: &mut Map,
+        path: &[Pos2],
+        modifiers: egui::Modifiers,
+    ) -> Vec<MapEvent> {
+        let button = |pos, pressed| egui::Event::PointerButton {
+            pos,
+            button: egui::PointerButton::Primary,
+            pressed,
+            modifiers,
+        };
+        let last = *path.last().unwrap();
+        let mut frames = vec![
+            vec![
+                egui::Event::ModifiersChanged(modifiers),
+                egui::Event::PointerMoved(path[0]),
+            ],
+            vec![button(path[0], true)],
+        ];
+        frames.extend(
+            path[1..]
+                .iter()
+                .map(|pos| vec![egui::Event::PointerMoved(*pos)]),
+        );
+        frames.push(vec![button(last, false)]);
+        frames.push(Vec::new());
+        let mut events = Vec::new();
+        for frame_events in frames {
+            events.extend(run_frame(ctx, map, frame_events).events);
+        }
+        events
+    }
+
+    #[test]
+    fn shift_drag_selects_nodes_inside_rectangle() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.set_pos([0.0, 0.0]);
+        run_frame(&ctx, &mut map, Vec::new());
+
+        // nodos: 1 en (250,250), 2 en (260,260), 3 en (240,240)
+        let path = [
+            Pos2::new(245.0, 245.0),
+            Pos2::new(255.0, 255.0),
+            Pos2::new(265.0, 265.0),
+        ];
+        let events = drag_along(&ctx, &mut map, &path, egui::Modifiers::SHIFT);
+        assert!(events.contains(&MapEvent::SelectionChanged));
+        assert_eq!(map.selected_nodes(), &HashSet::from([1, 2]));
+        // seleccionar no desplaza la vista
+        assert_eq!(map.get_pos(), [0.0, 0.0]);
+
+        // con Ctrl se añade a la selección existente
+        let path = [Pos2::new(235.0, 235.0), Pos2::new(245.0, 245.0)];
+        let modifiers = egui::Modifiers::SHIFT | egui::Modifiers::CTRL;
+        drag_along(&ctx, &mut map, &path, modifiers);
+        assert_eq!(map.selected_nodes(), &HashSet::from([1, 2, 3]));
+    }
+
+    #[test]
+    fn plain_drag_pans_unless_drag_behavior_is_select() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.set_pos([0.0, 0.0]);
+        run_frame(&ctx, &mut map, Vec::new());
+
+        let path = [Pos2::new(245.0, 245.0), Pos2::new(265.0, 265.0)];
+        drag_along(&ctx, &mut map, &path, egui::Modifiers::NONE);
+        assert!(map.selected_nodes().is_empty());
+        assert_ne!(map.get_pos(), [0.0, 0.0]);
+
+        map.set_pos([0.0, 0.0]);
+        map.settings.drag_behavior = DragBehavior::Select;
+        run_frame(&ctx, &mut map, Vec::new());
+        drag_along(&ctx, &mut map, &path, egui::Modifiers::NONE);
+        assert_eq!(map.selected_nodes(), &HashSet::from([1, 2]));
+        assert_eq!(map.get_pos(), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn lasso_selects_nodes_inside_outline() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.set_pos([0.0, 0.0]);
+        map.settings.selection_shape = SelectionShape::Lasso;
+        run_frame(&ctx, &mut map, Vec::new());
+
+        // triángulo que contiene el nodo 3 (240,240) y el 1 (250,250) pero no
+        // el 2 (260,260), aunque éste cae dentro de su caja envolvente
+        let path = [
+            Pos2::new(230.0, 230.0),
+            Pos2::new(275.0, 230.0),
+            Pos2::new(230.0, 275.0),
+        ];
+        drag_along(&ctx, &mut map, &path, egui::Modifiers::SHIFT);
+        assert_eq!(map.selected_nodes(), &HashSet::from([1, 3]));
+    }
+
+    #[test]
+    fn polygon_contains_uses_even_odd_rule() {
+        let square = [
+            RawPoint::new(0.0, 0.0),
+            RawPoint::new(10.0, 0.0),
+            RawPoint::new(10.0, 10.0),
+            RawPoint::new(0.0, 10.0),
+        ];
+        assert!(polygon_contains(&square, RawPoint::new(5.0, 5.0)));
+        assert!(!polygon_contains(&square, RawPoint::new(15.0, 5.0)));
+        assert!(!polygon_contains(&[], RawPoint::new(0.0, 0.0)));
+    }
+
+    // ---------- edición incremental de nodos ----------
+
+    #[test]
+    fn insert_point_indexes_without_moving_view() {
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.set_pos([3.0, 4.0]);
+
+        assert!(map.insert_point(MapPoint::new(4, [20.0, -30.0])).is_none());
+        assert_eq!(map.get_pos(), [3.0, 4.0]);
+        assert_eq!(map.reference.min.components, [-10.0, -30.0]);
+        assert_eq!(map.reference.max.components, [20.0, 10.0]);
+        assert_eq!(map.nearest_node(RawPoint::new(19.0, -29.0), None), Some(4));
+        assert!(map.visible_points.contains(&4));
+
+        // reemplazar un id existente devuelve el nodo anterior
+        let old = map.insert_point(MapPoint::new(4, [1.0, 1.0])).unwrap();
+        assert_eq!(old.coords, [20.0, -30.0]);
+        assert_eq!(map.reference.min.components, [-10.0, -10.0]);
+        assert_eq!(map.reference.max.components, [10.0, 10.0]);
+        assert_eq!(map.nearest_node(RawPoint::new(1.2, 1.2), None), Some(4));
+        // la entrada antigua ya no está en el índice
+        assert_eq!(map.tree.as_ref().unwrap().size(), 4);
+    }
+
+    #[test]
+    fn insert_point_starts_a_node_set() {
+        let mut map = Map::new();
+        map.insert_point(MapPoint::new(7, [2.0, 2.0]));
+        assert_eq!(map.reference.min.components, [2.0, 2.0]);
+        assert_eq!(map.reference.max.components, [2.0, 2.0]);
+        assert_eq!(map.visible_points, vec![7]);
+    }
+
+    #[test]
+    fn update_point_only_replaces_known_nodes() {
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        assert!(map.update_point(MapPoint::new(99, [0.0, 0.0])).is_none());
+        assert!(!map.points.as_ref().unwrap().contains_key(&99));
+
+        let mut updated = MapPoint::new(2, [5.0, 5.0]);
+        updated.set_name("Updated".to_string());
+        assert!(map.update_point(updated).is_some());
+        assert_eq!(map.points.as_ref().unwrap()[&2].coords, [5.0, 5.0]);
+        assert_eq!(map.reference.max.components, [5.0, 5.0]);
+    }
+
+    #[test]
+    fn move_point_updates_index_and_bounds() {
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.set_pos([1.0, 1.0]);
+
+        assert!(map.move_point(3, [-50.0, 0.0]));
+        assert_eq!(map.get_pos(), [1.0, 1.0]);
+        assert_eq!(map.reference.min.components, [-50.0, 0.0]);
+        assert_eq!(map.nearest_node(RawPoint::new(-49.0, 0.0), None), Some(3));
+        assert_eq!(map.nearest_node(RawPoint::new(-10.0, -10.0), None), Some(1));
+        assert_eq!(map.tree.as_ref().unwrap().size(), 3);
+
+        assert!(!map.move_point(99, [0.0, 0.0]));
+    }
+
+    #[test]
+    fn remove_point_drops_node_state() {
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.select_node(2);
+        map.notify(2, Instant::now());
+
+        assert_eq!(map.point(2).map(|p| p.coords), Some([10.0, 10.0]));
+        assert_eq!(map.points().count(), 3);
+        let removed = map.remove_point(2).unwrap();
+        assert_eq!(removed.coords, [10.0, 10.0]);
+        assert!(!map.is_node_selected(2));
+        assert!(!map.entities.contains_key(&2));
+        assert!(!map.visible_points.contains(&2));
+        assert_eq!(map.reference.max.components, [0.0, 0.0]);
+        assert_eq!(map.tree.as_ref().unwrap().size(), 2);
+        assert!(map.remove_point(2).is_none());
+        assert!(map.point(2).is_none());
+    }
+
+    // ---------- rutas y vecindad ----------
+
+    /// Cadena 1 - 2 - 3 con los puntos de ejemplo.
+    fn chained_points() -> Vec<MapPoint> {
+        let mut points = sample_points();
+        points[0].connections = vec![(1, 2)];
+        points[1].connections = vec![(1, 2), (2, 3)];
+        points[2].connections = vec![(2, 3)];
+        points
+    }
+
+    #[test]
+    fn shortest_path_and_neighborhood_use_loaded_connections() {
+        let mut map = Map::new();
+        map.add_points(chained_points());
+        let route = map.shortest_path(1, 3, RouteMetric::Hops).unwrap();
+        assert_eq!(route.nodes, vec![1, 2, 3]);
+        assert_eq!(map.neighborhood(2, 1).len(), 2);
+        assert!(Map::new().shortest_path(1, 3, RouteMetric::Hops).is_none());
+    }
+
+    #[test]
+    fn distance_highlight_tints_by_hops() {
+        let mut map = Map::new();
+        map.add_points(chained_points());
+        let style = map.current_style().clone();
+        map.set_distance_highlight(1, 1);
+        assert_eq!(map.highlighted_hops(1), Some(0));
+        assert_eq!(map.highlighted_hops(2), Some(1));
+        assert_eq!(map.highlighted_hops(3), None);
+
+        assert_eq!(map.node_fill_color(1), style.alert_color);
+        assert_eq!(
+            map.node_fill_color(2),
+            style.alert_color.lerp_to_gamma(style.fill_color, 0.5)
+        );
+        assert_eq!(map.node_fill_color(3), style.fill_color);
+
+        map.clear_distance_highlight();
+        assert_eq!(map.highlighted_hops(1), None);
+        assert_eq!(map.node_fill_color(1), style.fill_color);
+    }
+
+    // ---------- líneas derivadas ----------
+
+    fn line_ends(map: &Map, id: (usize, usize)) -> Option<[[f32; 2]; 2]> {
+        map.lines()
+            .find(|segment| segment.id == id)
+            .map(|segment| [segment.point1, segment.point2])
+    }
+
+    #[test]
+    fn lines_are_built_from_connections() {
+        let mut map = Map::new();
+        let mut points = chained_points();
+        // (3,9) sólo la lista el nodo 3 y el nodo 9 no existe
+        points[2].connections.push((3, 9));
+        map.add_points(points);
+        map.build_lines_from_connections();
+
+        assert_eq!(map.lines().count(), 2);
+        let ends = line_ends(&map, (1, 2)).unwrap();
+        assert!(ends.contains(&[0.0, 0.0]) && ends.contains(&[10.0, 10.0]));
+        assert!(line_ends(&map, (3, 9)).is_none());
+        assert!(map.validate().issues.iter().all(|issue| matches!(
+            issue,
+            ValidationIssue::DanglingConnection {
+                connection: (3, 9),
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn derived_lines_follow_node_edits() {
+        let mut map = Map::new();
+        map.add_points(chained_points());
+        map.build_lines_from_connections();
+
+        assert!(map.move_point(2, [20.0, 0.0]));
+        assert!(line_ends(&map, (1, 2)).unwrap().contains(&[20.0, 0.0]));
+        assert!(line_ends(&map, (2, 3)).unwrap().contains(&[20.0, 0.0]));
+        assert_eq!(map.lines().count(), 2);
+
+        // un nodo nuevo crea las líneas que sólo le faltaban a él
+        let mut fourth = MapPoint::new(4, [5.0, 5.0]);
+        fourth.connections = vec![(3, 4)];
+        map.insert_point(fourth);
+        assert!(line_ends(&map, (3, 4)).unwrap().contains(&[-10.0, -10.0]));
+
+        // reemplazar el nodo sin la conexión borra su línea
+        map.update_point(MapPoint::new(4, [5.0, 5.0]));
+        assert!(line_ends(&map, (3, 4)).is_none());
+
+        map.remove_point(2);
+        assert_eq!(map.lines().count(), 0);
+
+        // al volver a cargar nodos se reconstruyen las líneas
+        map.add_points(chained_points());
+        assert_eq!(map.lines().count(), 2);
+    }
+
+    #[test]
+    fn one_sided_lines_follow_the_unlisting_node() {
+        let mut map = Map::new();
+        let mut listing = MapPoint::new(1, [0.0, 0.0]);
+        listing.connections = vec![(1, 2)];
+        map.add_points(vec![listing, MapPoint::new(2, [10.0, 0.0])]);
+        map.build_lines_from_connections();
+        assert_eq!(line_ends(&map, (1, 2)).map(|ends| ends.len()), Some(2));
+
+        // el nodo 2 no lista la conexión, pero su línea lo sigue igual
+        map.move_point(2, [50.0, 50.0]);
+        assert!(line_ends(&map, (1, 2)).unwrap().contains(&[50.0, 50.0]));
+        map.insert_point(MapPoint::new(2, [60.0, 60.0]));
+        assert!(line_ends(&map, (1, 2)).unwrap().contains(&[60.0, 60.0]));
+
+        map.remove_point(2);
+        assert!(line_ends(&map, (1, 2)).is_none());
+        // y vuelve cuando el nodo reaparece
+        map.insert_point(MapPoint::new(2, [5.0, 5.0]));
+        assert!(line_ends(&map, (1, 2)).unwrap().contains(&[5.0, 5.0]));
+    }
+
+    #[test]
+    fn manual_lines_are_not_synced() {
+        let mut map = Map::new();
+        map.add_points(chained_points());
+        map.build_lines_from_connections();
+        map.add_lines(vec![MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0])]);
+
+        map.move_point(2, [20.0, 0.0]);
+        assert_eq!(line_ends(&map, (1, 2)), Some([[0.0, 0.0], [10.0, 10.0]]));
+        map.remove_point(1);
+        assert_eq!(map.lines().count(), 1);
+    }
+
+    // ---------- búsqueda ----------
+
+    fn named_points() -> Vec<MapPoint> {
+        let mut points = sample_points();
+        for (point, name) in points.iter_mut().zip(["Alpha", "Beta Prime", "Gamma"]) {
+            point.set_name(name.to_string());
+        }
+        points
+    }
+
+    #[test]
+    fn search_tracks_node_edits() {
+        let mut map = Map::new();
+        map.add_points(named_points());
+        assert_eq!(map.search("prime", 5)[0].id, 2);
+        assert!(map.search("delta", 5).is_empty());
+
+        let mut delta = MapPoint::new(4, [1.0, 1.0]);
+        delta.set_name("Delta".to_string());
+        map.insert_point(delta);
+        assert_eq!(map.search("delta", 5)[0].id, 4);
+
+        // un nodo reemplazado sin nombre deja de aparecer
+        map.insert_point(MapPoint::new(4, [1.0, 1.0]));
+        assert!(map.search("delta", 5).is_empty());
+
+        map.remove_point(2);
+        assert!(map.search("beta", 5).is_empty());
+        // recargar los nodos reconstruye el índice
+        map.add_points(named_points());
+        assert_eq!(map.search("beta", 5)[0].id, 2);
+    }
+
+    #[test]
+    fn search_box_jumps_to_first_match() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.add_points(named_points());
+        map.settings.search_box = SearchBox::Jump;
+        run_frame(&ctx, &mut map, vec![]);
+
+        // enfocar la caja, escribir y pulsar Enter
+        click_at(&ctx, &mut map, Pos2::new(40.0, 25.0));
+        run_frame(&ctx, &mut map, vec![egui::Event::Text("gam".to_string())]);
+        assert_eq!(map.search_results.len(), 1);
+        let enter = |pressed| egui::Event::Key {
+            key: Key::Enter,
+            physical_key: None,
+            pressed,
+            repeat: false,
+            modifiers: egui::Modifiers::NONE,
+        };
+        run_frame(&ctx, &mut map, vec![enter(true), enter(false)]);
+
+        assert_eq!(map.get_pos(), [-10.0, -10.0]);
+        assert!(map.search_query.is_empty());
+        assert!(map.search_results.is_empty());
+    }
+
+    // ---------- consultas espaciales ----------
+
+    #[test]
+    fn node_queries_use_map_coordinates() {
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        // las esquinas pueden darse en cualquier orden
+        let mut inside = map.nodes_in_rect([12.0, -1.0], [-1.0, 12.0]);
+        inside.sort();
+        assert_eq!(inside, vec![1, 2]);
+        assert!(map.nodes_in_rect([50.0, 50.0], [60.0, 60.0]).is_empty());
+
+        assert_eq!(map.nodes_within([9.0, 9.0], 15.0), vec![2, 1]);
+        assert_eq!(map.nodes_within([9.0, 9.0], 1.0), Vec::<usize>::new());
+        assert_eq!(map.nearest_nodes([-8.0, -8.0], 2, None), vec![3, 1]);
+        assert_eq!(map.nearest_nodes([-8.0, -8.0], 5, Some(3.0)), vec![3]);
+        assert!(map.nearest_nodes([0.0, 0.0], 0, None).is_empty());
+        assert!(Map::new().nearest_nodes([0.0, 0.0], 3, None).is_empty());
+    }
+
+    #[test]
+    fn lines_in_rect_checks_the_segment_not_its_box() {
+        let mut map = Map::new();
+        map.add_lines(vec![
+            MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0]),
+            MapSegment::new((2, 3), [-10.0, 0.0], [10.0, 0.0]),
+        ]);
+        // la esquina (8,0)-(10,2) está dentro de la caja de (1,2) pero no la cruza
+        assert_eq!(map.lines_in_rect([8.0, 0.5], [10.0, 2.0]), Vec::new());
+        let mut crossing = map.lines_in_rect([-1.0, -1.0], [1.0, 1.0]);
+        crossing.sort();
+        assert_eq!(crossing, vec![(1, 2), (2, 3)]);
+        assert_eq!(map.lines_in_rect([-5.0, -1.0], [-4.0, 1.0]), vec![(2, 3)]);
+    }
+
+    #[test]
+    fn visible_nodes_follow_the_view_and_layers() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        let mut points = layered_points();
+        points.push(MapPoint::new(4, [1000.0, 1000.0]));
+        map.add_points(points);
+        assert!(map.visible_nodes().is_empty());
+
+        map.set_pos([0.0, 0.0]);
+        map.set_zoom(1.0);
+        run_frame(&ctx, &mut map, vec![]);
+        let mut visible = map.visible_nodes();
+        visible.sort();
+        assert_eq!(visible, vec![1, 2, 3]);
+
+        map.add_node_layer(
+            "bottom",
+            NodeLayer {
+                visible: false,
+                ..NodeLayer::default()
+            },
+        );
+        let mut visible = map.visible_nodes();
+        visible.sort();
+        assert_eq!(visible, vec![1, 2]);
+        // las consultas sobre el índice incluyen las capas ocultas
+        assert_eq!(map.nodes_in_rect([-20.0, -20.0], [-5.0, -5.0]), vec![3]);
+    }
+
+    // ---------- conversión de coordenadas ----------
+
+    #[test]
+    fn coordinate_conversion_matches_last_frame() {
+        let ctx = egui::Context::default();
+        let mut map = Map::new();
+        map.add_points(sample_points());
+        map.set_pos([0.0, 0.0]);
+        map.set_zoom(1.0);
+        run_frame(&ctx, &mut map, vec![]);
+
+        assert_eq!(map.map_to_screen([0.0, 0.0]), Pos2::new(250.0, 250.0));
+        assert_eq!(map.map_to_screen([10.0, 10.0]), Pos2::new(260.0, 260.0));
+        assert_eq!(map.screen_to_map(Pos2::new(240.0, 240.0)), [-10.0, -10.0]);
+        let rect = map.visible_map_rect();
+        assert_eq!(rect.min, Pos2::new(-250.0, -250.0));
+        assert_eq!(rect.max, Pos2::new(250.0, 250.0));
+
+        // un cambio de zoom se refleja sin esperar al siguiente frame
+        map.set_zoom(2.0);
+        assert_eq!(map.map_to_screen([10.0, 10.0]), Pos2::new(270.0, 270.0));
+        let back = map.screen_to_map(map.map_to_screen([3.0, -4.0]));
+        assert!((back[0] - 3.0).abs() < 1e-4 && (back[1] + 4.0).abs() < 1e-4);
+
+        // el nodo pintado en esa posición es el que devuelve node_at
+        let screen = map.map_to_screen([-10.0, -10.0]);
+        assert_eq!(map.node_at(screen), Some(3));
+    }
+
+    // ---------- agrupación ----------
+
+    fn clustered_map() -> Map {
+        let mut map = Map::new();
+        map.add_points(vec![
+            MapPoint::new(1, [0.0, 0.0]),
+            MapPoint::new(2, [15.0, 0.0]),
+            MapPoint::new(3, [100.0, 100.0]),
+        ]);
+        map.settings.cluster_radius = 20.0;
+        map.set_pos([0.0, 0.0]);
+        map.set_zoom(1.0);
+        map
+    }
+
+    #[test]
+    fn close_nodes_are_clustered_until_zooming_in() {
+        let ctx = egui::Context::default();
+        let mut map = clustered_map();
+        run_frame(&ctx, &mut map, vec![]);
+        assert_eq!(
+            map.clusters(),
+            &[NodeCluster {
+                members: vec![1, 2],
+                center: [7.5, 0.0],
+            }]
+        );
+        let texts = render_texts(&mut map);
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0].0, "2");
+        // el nodo agrupado no se puede señalar por separado
+        assert_eq!(map.node_at(Pos2::new(250.0, 250.0)), None);
+
+        map.set_zoom(2.0);
+        run_frame(&ctx, &mut map, vec![]);
+        assert!(map.clusters().is_empty());
+        assert_eq!(map.node_at(Pos2::new(250.0, 250.0)), Some(1));
+
+        map.settings.cluster_radius = 0.0;
+        map.set_zoom(1.0);
+        run_frame(&ctx, &mut map, vec![]);
+        assert!(map.clusters().is_empty());
+    }
+
+    #[test]
+    fn clicking_a_cluster_fits_its_members() {
+        let ctx = egui::Context::default();
+        let mut map = clustered_map();
+        run_frame(&ctx, &mut map, vec![]);
+
+        let events = click_at(&ctx, &mut map, Pos2::new(257.5, 250.0));
+        assert!(events.contains(&MapEvent::ClusterClicked {
+            members: vec![1, 2],
+        }));
+        assert!(!events.contains(&MapEvent::SelectionChanged));
+        assert_eq!(map.get_pos(), [7.5, 0.0]);
+        assert!(map.get_zoom() > 1.0);
+    }
+
+    #[test]
+    fn nodes_closer_than_the_radius_always_cluster() {
+        let ctx = egui::Context::default();
+        let mut map = clustered_map();
+        // 4 y 5 quedan a 1 punto de distancia, a ambos lados de x = 60
+        for point in [
+            MapPoint::new(4, [59.5, 0.0]),
+            MapPoint::new(5, [60.5, 0.0]),
+            MapPoint::new(6, [-80.0, 0.0]),
+            MapPoint::new(7, [-61.0, 0.0]),
+            MapPoint::new(8, [-42.0, 0.0]),
+        ] {
+            map.insert_point(point);
+        }
+        run_frame(&ctx, &mut map, vec![]);
+        let members: Vec<&[usize]> = map
+            .clusters()
+            .iter()
+            .map(|cluster| cluster.members.as_slice())
+            .collect();
+        // una cadena de nodos cercanos forma un único grupo
+        assert_eq!(members, vec![&[1, 2][..], &[4, 5], &[6, 7, 8]]);
+    }
+
+    #[test]
+    fn clicking_a_cluster_at_max_zoom_reaches_its_nodes() {
+        let ctx = egui::Context::default();
+        let mut map = clustered_map();
+        map.move_point(2, [0.5, 0.0]);
+        run_frame(&ctx, &mut map, vec![]);
+        assert_eq!(map.clusters().len(), 1);
+
+        let events = click_at(&ctx, &mut map, Pos2::new(250.0, 250.0));
+        assert!(events.contains(&MapEvent::ClusterClicked {
+            members: vec![1, 2],
+        }));
+        assert_eq!(map.get_zoom(), map.settings.max_zoom);
+        run_frame(&ctx, &mut map, vec![]);
+        // en el zoom máximo no se agrupa y los nodos se pueden señalar
+        assert!(map.clusters().is_empty());
+        assert_eq!(map.node_at(Pos2::new(249.5, 250.0)), Some(1));
+        assert_eq!(map.node_at(Pos2::new(250.5, 250.0)), Some(2));
+    }
+
+    #[test]
+    fn cluster_template_replaces_the_bubble() {
+        struct Recording(Rc<std::cell::RefCell<Vec<usize>>>);
+        impl ClusterTemplate for Recording {
+            fn cluster_ui(&self, _ui: &mut Ui, _position: Pos2, _zoom: f32, cluster: &NodeCluster) {
+                self.0.borrow_mut().push(cluster.members.len());
+            }
+
+            fn hit_radius(&self, _zoom: f32, _cluster: &NodeCluster) -> f32 {
+                40.0
+            }
+        }
+        let drawn = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut map = clustered_map();
+        map.set_cluster_template(Rc::new(Recording(drawn.clone())));
+        assert!(render_texts(&mut map).is_empty());
+        assert_eq!(*drawn.borrow(), vec![2]);
+
+        // fuera de la burbuja por defecto pero dentro del radio de la plantilla
+        let ctx = egui::Context::default();
+        run_frame(&ctx, &mut map, vec![]);
+        let events = click_at(&ctx, &mut map, Pos2::new(257.5, 280.0));
+        assert!(events.contains(&MapEvent::ClusterClicked {
+            members: vec![1, 2],
+        }));
+    }
+
+    // ---------- jerarquía ----------
+
+    /// Una región (1) con un grupo (10) de dos nodos y un nodo suelto unido
+    /// al grupo.
+    fn hierarchy_map() -> Map {
+        let mut map = Map::new();
+        let mut a = MapPoint::new(1, [0.0, 0.0]);
+        a.set_group(10);
+        a.connections = vec![(1, 2), (1, 3)];
+        let mut b = MapPoint::new(2, [20.0, 0.0]);
+        b.set_group(10);
+        b.connections = vec![(1, 2)];
+        let mut c = MapPoint::new(3, [100.0, 0.0]);
+        c.connections = vec![(1, 3)];
+        map.add_points(vec![a, b, c]);
+        map.build_lines_from_connections();
+        map.add_node_group(1, NodeGroup::new("Region", 0.5));
+        map.add_node_group(
+            10,
+            NodeGroup {
+                parent: Some(1),
+                ..NodeGroup::new("North", 1.5)
+            },
+        );
+        map.set_pos([0.0, 0.0]);
+        map.set_zoom(1.0);
+        map
+    }
+
+    #[test]
+    fn collapsed_groups_replace_their_nodes_and_lines() {
+        let ctx = egui::Context::default();
+        let mut map = hierarchy_map();
+        run_frame(&ctx, &mut map, vec![]);
+        assert_eq!(map.collapsed_groups(), vec![10]);
+        assert_eq!(map.node_at(Pos2::new(250.0, 250.0)), None);
+        let texts = render_texts(&mut map);
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0].0, "North");
+        // las líneas de los nodos plegados se funden en una sola
+        assert_eq!(
+            render_line_segments(&mut map),
+            vec![[Pos2::new(350.0, 250.0), Pos2::new(260.0, 250.0)]]
+        );
+
+        map.set_zoom(0.3);
+        run_frame(&ctx, &mut map, vec![]);
+        assert_eq!(map.collapsed_groups(), vec![1]);
+
+        map.set_zoom(2.0);
+        run_frame(&ctx, &mut map, vec![]);
+        assert!(map.collapsed_groups().is_empty());
+        assert_eq!(map.node_at(Pos2::new(250.0, 250.0)), Some(1));
+        assert_eq!(render_line_segments(&mut map).len(), 2);
+
+        // las líneas agregadas fuera de la vista no se dibujan
+        map.set_zoom(1.0);
+        map.set_pos([1000.0, 1000.0]);
+        assert!(render_line_segments(&mut map).is_empty());
+    }
+
+    #[test]
+    fn clicking_a_group_expands_it() {
+        let ctx = egui::Context::default();
+        let mut map = hierarchy_map();
+        run_frame(&ctx, &mut map, vec![]);
+
+        let events = click_at(&ctx, &mut map, Pos2::new(255.0, 250.0));
+        assert!(events.contains(&MapEvent::GroupClicked { id: 10 }));
+        assert!(!events.contains(&MapEvent::SelectionChanged));
+        assert_eq!(map.get_pos(), [10.0, 0.0]);
+        assert_eq!(map.get_zoom(), 1.5);
+        run_frame(&ctx, &mut map, vec![]);
+        assert!(map.collapsed_groups().is_empty());
+    }
+
+    #[test]
+    fn group_view_follows_node_and_group_changes() {
+        let ctx = egui::Context::default();
+        let mut map = hierarchy_map();
+        run_frame(&ctx, &mut map, vec![]);
+        map.move_point(2, [40.0, 0.0]);
+        run_frame(&ctx, &mut map, vec![]);
+        assert_eq!(
+            map.group_view.as_ref().unwrap().shapes[0].center,
+            [20.0, 0.0]
+        );
+
+        // un nodo ajeno a los grupos no obliga a recalcular la vista
+        map.insert_point(MapPoint::new(4, [-50.0, 0.0]));
+        map.move_point(4, [-60.0, 0.0]);
+        assert!(map.group_view.is_some());
+        // el extremo de una línea agregada sí
+        map.move_point(3, [100.0, 10.0]);
+        assert!(map.group_view.is_none());
+        run_frame(&ctx, &mut map, vec![]);
+        assert_eq!(
+            map.group_view.as_ref().unwrap().edges,
+            vec![[[100.0, 10.0], [20.0, 0.0]]]
+        );
+
+        assert_eq!(map.remove_node_group(10).unwrap().name, "North");
+        run_frame(&ctx, &mut map, vec![]);
+        // sin el grupo 10, sus nodos quedan fuera de la región
+        assert_eq!(map.collapsed_groups(), Vec::<usize>::new());
+        assert_eq!(map.node_at(Pos2::new(250.0, 250.0)), Some(1));
+        assert!(map.node_group(1).is_some());
+        map.clear_node_groups();
+        assert!(map.node_group(1).is_none());
+    }
+
+    // ---------- validación ----------
+
+    #[test]
+    fn validate_reports_load_and_graph_issues() {
+        let mut map = Map::new();
+        let mut points = chained_points();
+        points.push(MapPoint::new(1, [0.0, 0.0]));
+        map.add_points(points);
+        map.add_lines(vec![
+            MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0]),
+            MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0]),
+        ]);
+        let report = map.validate();
+        // el nodo 1 repetido perdió sus conexiones y la (2,3) no tiene línea
+        for expected in [
+            ValidationIssue::DuplicateNodeId { id: 1 },
+            ValidationIssue::DuplicateSegmentId { id: (1, 2) },
+            ValidationIssue::DanglingConnection {
+                node: 2,
+                connection: (2, 3),
+            },
+            ValidationIssue::DanglingConnection {
+                node: 3,
+                connection: (2, 3),
+            },
+        ] {
+            assert!(report.issues.contains(&expected), "falta {expected:?}");
+        }
+        assert_eq!(report.issues.len(), 4);
+
+        // con datos correctos el informe queda vacío
+        map.add_points(chained_points());
+        map.add_lines(vec![
+            MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0]),
+            MapSegment::new((2, 3), [10.0, 10.0], [-10.0, -10.0]),
+        ]);
+        assert!(map.validate().is_ok());
+    }
+
+    #[test]
+    fn incremental_edits_clear_load_issues() {
+        let mut map = Map::new();
+        let mut points = chained_points();
+        points.push(MapPoint::new(1, [0.0, 0.0]));
+        map.add_points(points);
+        map.add_lines(vec![
+            MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0]),
+            MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0]),
+        ]);
+        // de las líneas repetidas solo queda la última
+        assert_eq!(map.lines().count(), 1);
+
+        let node = chained_points().remove(0);
+        map.insert_point(node);
+        map.insert_line(MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0]));
+        map.insert_line(MapSegment::new((2, 3), [10.0, 10.0], [-10.0, -10.0]));
+        assert!(map.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_reports_mismatched_hashmap_keys() {
+        let mut map = Map::new();
+        map.add_hashmap_points(HashMap::from([(5, MapPoint::new(1, [0.0, 0.0]))]));
+        map.add_hashmap_lines(HashMap::from([(
+            (1, 1),
+            MapSegment::new((2, 2), [0.0, 0.0], [0.0, 0.0]),
+        )]));
+        let issues = map.validate().issues;
+        assert!(issues.contains(&ValidationIssue::MismatchedNodeKey { key: 5, id: 1 }));
+        assert!(issues.contains(&ValidationIssue::MismatchedSegmentKey {
+            key: (1, 1),
+            id: (2, 2)
+        }));
+    }
+
+    #[test]
+    fn strict_loaders_reject_inconsistent_data() {
+        let mut map = Map::new();
+        let bad = vec![MapPoint::new(1, [f32::NAN, 0.0])];
+        let report = map.try_add_points(bad).unwrap_err();
+        assert_eq!(
+            report.issues,
+            vec![ValidationIssue::NonFiniteNode { id: 1 }]
+        );
+        assert!(map.points.is_none());
+
+        map.try_add_points(chained_points()).unwrap();
+        let report = map
+            .try_add_lines(vec![MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0])])
+            .unwrap_err();
+        assert_eq!(report.issues.len(), 2);
+        assert!(map.segments.is_none());
+
+        map.try_add_lines(vec![
+            MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0]),
+            MapSegment::new((2, 3), [10.0, 10.0], [-10.0, -10.0]),
+        ])
+        .unwrap();
+        assert_eq!(map.lines().count(), 2);
+    }
+
+    // ---------- capas ----------
+
+    /// Registra el orden en que se dibujan los nodos.
+    struct RecordingTemplate(Rc<std::cell::RefCell<Vec<usize>>>);
+
+    impl NodeTemplate for RecordingTemplate {
+        fn node_ui(&self, _ui: &mut Ui, _position: Pos2, _zoom: f32, point: &MapPoint) {
+            self.0.borrow_mut().push(point.get_id());
+        }
+        fn selection_ui(&self, _ui: &mut Ui, _position: Pos2, _zoom: f32) {}
+        fn notification_ui(
+            &self,
+            _ui: &mut Ui,
+            _position: Pos2,
+            _zoom: f32,
+            _initial_time: Instant,
+            _color: Color32,
+        ) -> bool {
+            false
+        }
+        fn marker_ui(&self, _ui: &mut Ui, _position: Pos2, _zoom: f32) {}
+    }
+
+    fn layered_points() -> Vec<MapPoint> {
+        let mut points = sample_points();
+        points[1].set_layer("top".to_string());
+        points[2].set_layer("bottom".to_string());
+        points
+    }
+
+    #[test]
+    fn layers_are_painted_by_z_order_with_their_template() {
+        let ctx = egui::Context::default();
+        let painted = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut map = Map::new();
+        map.add_points(layered_points());
+        map.set_node_template(Rc::new(RecordingTemplate(painted.clone())));
+        map.add_node_layer(
+            "top",
+            NodeLayer {
+                z_order: 5,
+                ..NodeLayer::default()
+            },
+        );
+        map.add_node_layer(
+            "bottom",
+            NodeLayer {
+                z_order: -5,
+                ..NodeLayer::default()
+            },
+        );
+        run_frame(&ctx, &mut map, Vec::new());
+        assert_eq!(*painted.borrow(), vec![3, 1, 2]);
+
+        // ocultar una capa deja de dibujar sus nodos
+        painted.borrow_mut().clear();
+        assert!(map.set_node_layer_visible("top", false));
+        assert!(!map.set_node_layer_visible("missing", false));
+        run_frame(&ctx, &mut map, Vec::new());
+        assert_eq!(*painted.borrow(), vec![3, 1]);
+
+        // una plantilla propia de la capa reemplaza la del mapa
+        let own = Rc::new(std::cell::RefCell::new(Vec::new()));
+        painted.borrow_mut().clear();
+        map.node_layer_mut("bottom").unwrap().template =
+            Some(Rc::new(RecordingTemplate(own.clone())));
+        run_frame(&ctx, &mut map, Vec::new());
+        assert_eq!(*painted.borrow(), vec![1]);
+        assert_eq!(*own.borrow(), vec![3]);
+    }
+
+    #[test]
+    fn hidden_layers_are_not_picked() {
+        let ctx = egui::Context::default();
         let mut map = Map::new();
-        let initial = map.get_zoom();
-        map.set_zoom(0.05); // por debajo de min_zoom
-        assert_eq!(map.get_zoom(), initial);
-        map.set_zoom(2.5); // por encima de max_zoom
-        assert_eq!(map.get_zoom(), initial);
+        map.add_points(layered_points());
+        map.set_pos([0.0, 0.0]);
+        map.add_node_layer(
+            "top",
+            NodeLayer {
+                min_zoom: 1.5,
+                ..NodeLayer::default()
+            },
+        );
+        run_frame(&ctx, &mut map, Vec::new());
+
+        // el nodo 2 está en (260,260) pero su capa no se ve con zoom 1
+        let events = click_at(&ctx, &mut map, Pos2::new(260.0, 260.0));
+        assert!(!events.contains(&MapEvent::NodeClicked { id: 2 }));
+        assert!(!map.is_node_selected(2));
+        assert_eq!(map.nearest_node(RawPoint::new(10.0, 10.0), None), Some(1));
+        assert!(
+            map.nodes_in_bounds(RawPoint::new(5.0, 5.0), RawPoint::new(15.0, 15.0))
+                .is_empty()
+        );
+
+        map.set_zoom(2.0);
+        assert_eq!(map.nearest_node(RawPoint::new(10.0, 10.0), None), Some(2));
+
+        // quitar la capa devuelve sus nodos a la capa por defecto
+        map.set_zoom(1.0);
+        assert!(map.remove_node_layer("top").is_some());
+        assert_eq!(map.nearest_node(RawPoint::new(10.0, 10.0), None), Some(2));
     }
 
-    // ---------- puntos ----------
+    // ---------- capas personalizadas ----------
 
-    #[test]
-    fn add_hashmap_points_computes_bounds() {
-        let mut map = Map::new();
-        map.add_points(sample_points());
+    /// Anota `tag` cada vez que se dibuja, junto con la transformación.
+    struct RecordingLayer {
+        tag: usize,
+        painted: Rc<std::cell::RefCell<Vec<usize>>>,
+        transform: Rc<std::cell::Cell<Option<(MapTransform, Rect)>>>,
+    }
 
-        assert_eq!(map.reference.min.components, [-10.0, -10.0]);
-        assert_eq!(map.reference.max.components, [10.0, 10.0]);
-        // pos es el punto medio del rectángulo que contiene todos los puntos
-        assert_eq!(map.reference.pos.components, [0.0, 0.0]);
-        // map_area tiene área 0 antes de renderizar, así que dist es el valor fijo
-        assert_eq!(map.reference.dist, 3000.0);
-        // current se inicializa como copia de reference
-        assert_eq!(map.current.min.components, map.reference.min.components);
-        assert_eq!(map.current.max.components, map.reference.max.components);
-        assert_eq!(map.current.pos.components, map.reference.pos.components);
-        assert_eq!(map.current.dist, map.reference.dist);
-        assert!(map.points.is_some());
-        assert!(map.tree.is_some());
-        assert_eq!(map.points.as_ref().unwrap().len(), 3);
+    impl MapLayer for RecordingLayer {
+        fn paint(&self, _painter: &Painter, transform: &MapTransform, visible: Rect) {
+            self.painted.borrow_mut().push(self.tag);
+            self.transform.set(Some((*transform, visible)));
+        }
     }
 
     #[test]
-    fn add_hashmap_points_populates_visible_points() {
+    fn map_layers_are_interleaved_by_z_index() {
+        let ctx = egui::Context::default();
+        let painted = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let transform = Rc::new(std::cell::Cell::new(None));
+        let layer = |tag| {
+            Rc::new(RecordingLayer {
+                tag,
+                painted: painted.clone(),
+                transform: transform.clone(),
+            })
+        };
         let mut map = Map::new();
         map.add_points(sample_points());
-        // todos los puntos de muestra caen dentro del radio por defecto
-        assert_eq!(map.visible_points.len(), 3);
+        map.set_pos([0.0, 0.0]);
+        map.set_node_template(Rc::new(RecordingTemplate(painted.clone())));
+        let above_markers = map.add_map_layer(Map::MARKERS_Z, layer(1001));
+        map.add_map_layer(Map::NODES_Z - 1, layer(1000));
+        map.add_map_layer(Map::NODES_Z - 1, layer(1003));
+        map.add_map_layer(Map::LABELS_Z - 1, layer(1002));
+        run_frame(&ctx, &mut map, Vec::new());
+
+        let painted_ids = painted.borrow().clone();
+        assert_eq!(painted_ids[..3], [1002, 1000, 1003]);
+        assert_eq!(painted_ids.last(), Some(&1001));
+        assert_eq!(painted_ids.len(), 7);
+
+        let (transform, visible) = transform.get().unwrap();
+        assert_eq!(transform.zoom, 1.0);
+        let center = transform.map_to_screen([0.0, 0.0]);
+        assert_eq!(center, map.map_area.center());
+        assert!(visible.contains(Pos2::new(0.0, 0.0)));
+
+        assert!(map.remove_map_layer(above_markers).is_some());
+        assert!(map.remove_map_layer(above_markers).is_none());
+        painted.borrow_mut().clear();
+        run_frame(&ctx, &mut map, Vec::new());
+        assert!(!painted.borrow().contains(&1001));
+        map.clear_map_layers();
+        assert!(map.map_layers.is_empty());
     }
 
-    /// Renders one frame of `map` in a 500x500 viewport and returns the
-    /// painted line segments.
-    fn render_line_segments(map: &mut Map) -> Vec<[egui::Pos2; 2]> {
-        use egui::{Context, RawInput, Shape};
-        let ctx = Context::default();
-        let input = RawInput {
+    /// Dibuja un frame y devuelve los rectángulos de las mallas con textura
+    /// propia (no la de las fuentes), en el orden en que se pintan.
+    fn render_image_rects(ctx: &egui::Context, map: &mut Map) -> Vec<Rect> {
+        let input = egui::RawInput {
             screen_rect: Some(egui::Rect::from_min_size(
                 egui::Pos2::ZERO,
                 egui::vec2(500.0, 500.0),
             )),
-            ..RawInput::default()
+            ..egui::RawInput::default()
         };
-        let output = ctx.run_ui(input, |ui| {
+        let mut output = ctx.run_ui(input, |ui| {
             ui.add(&mut *map);
         });
+        output.textures_delta.clear();
         output
             .shapes
             .iter()
-            .filter_map(|cs| match cs.shape {
-                Shape::LineSegment { points, .. } => Some(points),
+            .filter_map(|cs| match &cs.shape {
+                egui::Shape::Mesh(mesh) if mesh.texture_id != egui::TextureId::default() => {
+                    Some(mesh.calc_bounds())
+                }
                 _ => None,
             })
             .collect()
     }
 
     #[test]
-    fn segment_crossing_viewport_is_painted_even_with_far_endpoints() {
-        // With the old endpoint-based rule this line was culled: both
-        // endpoints sit beyond the point-culling radius. With the R-tree the
-        // segment AABB intersects the viewport, so it is painted — no points
-        // needed at all.
+    fn image_overlays_follow_pan_and_zoom() {
+        let ctx = egui::Context::default();
+        let texture = ctx.load_texture(
+            "backdrop",
+            egui::ColorImage::new([2, 2], vec![Color32::RED; 4]),
+            TextureOptions::LINEAR,
+        );
         let mut map = Map::new();
-        map.set_zoom(1.0);
-        let mut lines = Vec::new();
-        lines.push(MapSegment::new((1, 2), [-4000.0, -1.0], [4000.0, 1.0]));
-        map.add_lines(lines);
+        map.add_points(sample_points());
         map.set_pos([0.0, 0.0]);
+        map.set_zoom(1.0);
+        // las esquinas pueden darse en cualquier orden
+        let id = map.add_image_overlay(&texture, [20.0, 10.0], [-20.0, -10.0], 0.5);
+        run_frame(&ctx, &mut map, Vec::new());
+        assert_eq!(
+            render_image_rects(&ctx, &mut map),
+            vec![Rect::from_min_max(
+                Pos2::new(230.0, 240.0),
+                Pos2::new(270.0, 260.0)
+            )]
+        );
 
-        let segments = render_line_segments(&mut map);
-        assert_eq!(segments.len(), 1);
-    }
+        map.set_zoom(2.0);
+        map.set_pos([10.0, 0.0]);
+        assert_eq!(
+            render_image_rects(&ctx, &mut map),
+            vec![Rect::from_min_max(
+                Pos2::new(190.0, 230.0),
+                Pos2::new(270.0, 270.0)
+            )]
+        );
 
-    #[test]
-    fn segment_outside_viewport_is_not_painted() {
-        let mut map = Map::new();
-        map.set_zoom(1.0);
-        let mut lines = Vec::new();
-        lines.push(MapSegment::new(
-            (1, 2),
-            [10_000.0, 10_000.0],
-            [10_100.0, 10_100.0],
-        ));
-        map.add_lines(lines);
+        // fuera de la vista o transparente no se dibuja
+        map.set_pos([1000.0, 1000.0]);
+        assert!(render_image_rects(&ctx, &mut map).is_empty());
         map.set_pos([0.0, 0.0]);
-
-        assert!(render_line_segments(&mut map).is_empty());
+        assert!(map.set_image_overlay_opacity(id, 0.0));
+        assert!(render_image_rects(&ctx, &mut map).is_empty());
+
+        assert!(map.remove_image_overlay(id));
+        assert!(!map.remove_image_overlay(id));
+        assert!(!map.set_image_overlay_opacity(id, 1.0));
+        map.add_image_overlay(&texture, [0.0, 0.0], [1.0, 1.0], 1.0);
+        map.clear_image_overlays();
+        assert!(map.image_overlays.is_empty());
     }
 
     #[test]
-    fn add_lines_builds_segment_tree() {
+    fn image_overlays_decode_bytes_with_the_installed_loaders() {
+        const SVG: &[u8] = br#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="4"><rect width="4" height="4" fill="red"/></svg>"#;
+        let ctx = egui::Context::default();
         let mut map = Map::new();
         map.add_points(sample_points());
-        let mut lines = Vec::new();
-        lines.push(MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0]));
-        map.add_lines(lines);
-
-        let tree = map
-            .segments
-            .as_ref()
-            .expect("add_lines must build the segment tree");
-        assert_eq!(tree.size(), 1);
-
-        // Broad-phase query: a viewport containing (0,0) must hit the segment;
-        // a far-away viewport must not.
-        let hit_query = rstar::AABB::from_corners([-1.0, -1.0], [1.0, 1.0]);
-        let hits: Vec<_> = tree.locate_in_envelope_intersecting(hit_query).collect();
-        assert_eq!(hits.len(), 1);
-        assert_eq!(hits[0].id, (1, 2));
-
-        let miss_query = rstar::AABB::from_corners([100.0, 100.0], [200.0, 200.0]);
-        assert_eq!(tree.locate_in_envelope_intersecting(miss_query).count(), 0);
-    }
-
-    #[test]
-    fn map_check_line_is_painted_on_first_frame() {
-        use egui::{Context, RawInput, Shape};
-
-        // --- arrange ---
-        let mut map = Map::new();
+        map.set_pos([0.0, 0.0]);
         map.set_zoom(1.0);
-
-        let mut point_a = MapPoint::new(0, [0.0, 0.0]);
-        point_a.connections.push((0, 1));
-        let mut point_b = MapPoint::new(1, [50.0, 50.0]);
-        point_b.connections.push((0, 1));
-
-        let mut lines = Vec::new();
-        lines.push(MapSegment::new((0, 1), point_a.coords, point_b.coords));
-
-        let mut points = Vec::new();
-        points.push(point_a);
-        points.push(point_b);
-        // Load points before lines — the natural order shown in the examples.
-        map.add_points(points);
-        map.add_lines(lines);
-
-        map.set_pos([25.0, 25.0]);
-
-        // --- act: 1st frame (no CentralPanel — run_ui creates the root Ui) ---
-        let ctx = Context::default();
-        let screen = egui::Rect::from_min_size(egui::Pos2::ZERO, egui::vec2(500.0, 500.0));
-        let input = RawInput {
-            screen_rect: Some(screen),
-            ..RawInput::default()
-        };
-
-        let output1 = ctx.run_ui(input.clone(), |ui| {
-            ui.add(&mut map);
-        });
-
-        let segments1: Vec<[egui::Pos2; 2]> = output1
-            .shapes
-            .iter()
-            .filter_map(|cs| match cs.shape {
-                Shape::LineSegment { points, .. } => Some(points),
-                _ => None,
-            })
-            .collect();
-
-        assert!(
-            !segments1.is_empty(),
-            "Frame 1: no LineSegment shapes painted (map lines did not draw)"
-        );
-
-        // Expected projection of (0,0)->(50,50) with zoom=1, center=(25,25),
-        // viewport 500x500: pos_a = (225, 225), pos_b = (275, 275). Tolerance ±2 px.
-        let expected_a = egui::pos2(225.0, 225.0);
-        let expected_b = egui::pos2(275.0, 275.0);
-        let tolerance = 2.0;
-        let found_on_frame1 = segments1.iter().any(|[p1, p2]| {
-            let d_a1 = p1.distance(expected_a);
-            let d_b1 = p2.distance(expected_b);
-            let d_a2 = p2.distance(expected_a);
-            let d_b2 = p1.distance(expected_b);
-            (d_a1 < tolerance && d_b1 < tolerance) || (d_a2 < tolerance && d_b2 < tolerance)
-        });
-        assert!(
-            found_on_frame1,
-            "Frame 1: no LineSegment matches expected endpoints (~225,225 -> ~275,275); got {:?}",
-            segments1
+        map.add_image_overlay(
+            ("bytes://backdrop.svg", SVG),
+            [-10.0, -10.0],
+            [10.0, 10.0],
+            1.0,
         );
+        run_frame(&ctx, &mut map, Vec::new());
+        // sin cargadores no hay con qué decodificar los bytes
+        assert!(render_image_rects(&ctx, &mut map).is_empty());
+        assert!(map.image_overlays[0].load_failed.get());
 
-        // --- act: 2nd frame (unchanged) — detect duplicate-lines regression ---
-        let output2 = ctx.run_ui(input, |ui| {
-            ui.add(&mut map);
-        });
-
-        let segments2: Vec<[egui::Pos2; 2]> = output2
-            .shapes
-            .iter()
-            .filter_map(|cs| match cs.shape {
-                Shape::LineSegment { points, .. } => Some(points),
-                _ => None,
-            })
-            .collect();
-
+        egui_extras::install_image_loaders(&ctx);
+        ctx.forget_all_images();
         assert_eq!(
-            segments1.len(),
-            segments2.len(),
-            "Frame 2: expected {} line segments (no duplication across frames), got {}",
-            segments1.len(),
-            segments2.len()
+            render_image_rects(&ctx, &mut map),
+            vec![Rect::from_min_max(
+                Pos2::new(240.0, 240.0),
+                Pos2::new(260.0, 260.0)
+            )]
         );
     }
 
@@ -1302,20 +5057,134 @@ mod tests {
     #[test]
     fn add_labels_stores_labels() {
         let mut map = Map::new();
-        let label = MapLabel {
-            text: "Region".to_string(),
-            center: Pos2::new(1.0, 2.0),
-        };
+        let label = MapLabel::at("Region", [1.0, 2.0]);
         map.add_labels(vec![label]);
         assert_eq!(map.labels.len(), 1);
         assert_eq!(map.labels[0].text, "Region");
     }
 
+    /// Pinta un frame y devuelve los textos no vacíos pintados: texto,
+    /// rectángulo sin rotar y ángulo.
+    fn render_texts(map: &mut Map) -> Vec<(String, Rect, f32)> {
+        let ctx = egui::Context::default();
+        let input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(500.0, 500.0),
+            )),
+            ..egui::RawInput::default()
+        };
+        let mut output = ctx.run_ui(input, |ui| {
+            ui.add(&mut *map);
+        });
+        output.textures_delta.clear();
+        output
+            .shapes
+            .iter()
+            .filter_map(|cs| match &cs.shape {
+                egui::Shape::Text(text) if !text.galley.text().is_empty() => Some((
+                    text.galley.text().to_string(),
+                    Rect::from_min_size(text.pos, text.galley.size()),
+                    text.angle,
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn labels_follow_the_map_within_their_zoom_range() {
+        let mut map = Map::new();
+        map.set_zoom(0.1);
+        map.add_labels(vec![
+            MapLabel {
+                priority: 5,
+                ..MapLabel::at("Forge", [100.0, 0.0])
+            },
+            MapLabel {
+                min_zoom: 0.5,
+                max_zoom: Some(2.0),
+                ..MapLabel::at("Jita", [0.0, 0.0])
+            },
+            MapLabel {
+                rotation: 0.5,
+                font: Some(FontId::proportional(30.0)),
+                ..MapLabel::at("Domain", [0.0, -100.0])
+            },
+        ]);
+        map.set_pos([0.0, 0.0]);
+
+        let texts = render_texts(&mut map);
+        let names: Vec<&str> = texts.iter().map(|(text, _, _)| text.as_str()).collect();
+        // la prioridad más alta se pinta encima, es decir, al final
+        assert_eq!(names, vec!["Domain", "Forge"]);
+        let forge = texts[1].1.center();
+        assert!((forge.x - 260.0).abs() < 0.01 && (forge.y - 250.0).abs() < 0.01);
+        assert_eq!(texts[0].2, 0.5);
+
+        // al acercarse se ocultan las etiquetas por defecto y aparece la otra
+        map.set_zoom(1.0);
+        let texts = render_texts(&mut map);
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0].0, "Jita");
+    }
+
+    fn crowded_map(declutter: bool) -> Map {
+        let mut map = Map::new();
+        let mut points = named_points();
+        // el nodo 2 queda justo encima del 1
+        points[1].coords = [0.0, -3.0];
+        points[2].coords = [-150.0, 150.0];
+        map.add_points(points);
+        map.settings.declutter_labels = declutter;
+        map.set_pos([0.0, 0.0]);
+        map.set_zoom(1.0);
+        map
+    }
+
+    #[test]
+    fn overlapping_node_names_move_apart_when_decluttering() {
+        let texts = render_texts(&mut crowded_map(false));
+        let rect_of = |name: &str| texts.iter().find(|(text, _, _)| text == name).unwrap().1;
+        assert!(rect_of("Alpha").intersects(rect_of("Beta Prime")));
+
+        let texts = render_texts(&mut crowded_map(true));
+        let names: HashSet<&str> = texts.iter().map(|(text, _, _)| text.as_str()).collect();
+        assert_eq!(names, HashSet::from(["Alpha", "Beta Prime", "Gamma"]));
+        for (i, (_, a, _)) in texts.iter().enumerate() {
+            for (_, b, _) in &texts[i + 1..] {
+                assert!(!a.shrink(0.5).intersects(b.shrink(0.5)));
+            }
+        }
+    }
+
+    #[test]
+    fn lower_priority_labels_make_way() {
+        let banner = |priority| MapLabel {
+            max_zoom: Some(2.0),
+            font: Some(FontId::proportional(60.0)),
+            priority,
+            ..MapLabel::at("BANNER", [0.0, -1.5])
+        };
+        let mut map = crowded_map(true);
+        map.add_labels(vec![banner(5)]);
+        let names: Vec<String> = render_texts(&mut map)
+            .into_iter()
+            .map(|(text, _, _)| text)
+            .collect();
+        assert_eq!(names, vec!["BANNER", "Gamma"]);
+
+        // con prioridad negativa es la etiqueta la que desaparece
+        map.add_labels(vec![banner(-1)]);
+        let texts = render_texts(&mut map);
+        assert_eq!(texts.len(), 3);
+        assert!(texts.iter().all(|(text, _, _)| text != "BANNER"));
+    }
+
     #[test]
     fn add_lines_stores_lines() {
         let mut map = Map::new();
-        let mut lines = Vec::new();
-        lines.push(MapSegment::new((1, 2), [0.0, 0.0], [1.0, 1.0]));
+        let lines = vec![MapSegment::new((1, 2), [0.0, 0.0], [1.0, 1.0])];
         map.add_lines(lines);
         let tree = map.segments.as_ref().unwrap();
         assert_eq!(tree.size(), 1);
@@ -1331,15 +5200,118 @@ mod tests {
         );
     }
 
+    #[test]
+    fn insert_update_and_remove_lines() {
+        let mut map = Map::new();
+        // sin líneas cargadas se crea el árbol
+        assert!(
+            map.insert_line(MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0]))
+                .is_none()
+        );
+        map.insert_line(MapSegment::new((1, 3), [0.0, 0.0], [-10.0, -10.0]));
+        assert_eq!(map.lines().count(), 2);
+        assert_eq!(map.line_at([5.0, 5.0], 0.5), Some((1, 2)));
+
+        // insertar con un id existente reemplaza la línea
+        let old = map
+            .insert_line(MapSegment::new((1, 2), [0.0, 0.0], [10.0, 0.0]))
+            .unwrap();
+        assert_eq!(old.point2, [10.0, 10.0]);
+        assert_eq!(map.lines().count(), 2);
+        assert_eq!(map.line_at([5.0, 5.0], 0.5), None);
+        assert_eq!(map.line_at([5.0, 0.0], 0.5), Some((1, 2)));
+
+        // update solo modifica líneas conocidas
+        assert!(
+            map.update_line(MapSegment::new((2, 3), [0.0, 0.0], [1.0, 1.0]))
+                .is_none()
+        );
+        assert_eq!(map.lines().count(), 2);
+        assert!(
+            map.update_line(MapSegment::new((1, 3), [0.0, 0.0], [0.0, -10.0]))
+                .is_some()
+        );
+        assert_eq!(map.line_at([0.0, -5.0], 0.5), Some((1, 3)));
+
+        assert_eq!(map.remove_line((1, 3)).unwrap().id, (1, 3));
+        assert!(map.remove_line((1, 3)).is_none());
+        let ids: Vec<_> = map.lines().map(|line| line.id).collect();
+        assert_eq!(ids, vec![(1, 2)]);
+
+        // las líneas cargadas en bloque también se localizan por id
+        map.add_lines(vec![
+            MapSegment::new((4, 5), [0.0, 0.0], [1.0, 0.0]),
+            MapSegment::new((5, 6), [1.0, 0.0], [2.0, 0.0]),
+        ]);
+        assert_eq!(map.remove_line((5, 6)).unwrap().point2, [2.0, 0.0]);
+        assert!(map.remove_line((1, 2)).is_none());
+        assert_eq!(map.lines().count(), 1);
+    }
+
+    #[test]
+    fn routes_are_drawn_below_line_visible_zoom() {
+        let mut map = Map::new();
+        map.settings.line_visible_zoom = 5.0;
+        map.add_points(sample_points());
+        map.add_lines(vec![MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0])]);
+        map.set_pos([0.0, 0.0]);
+        // la línea normal no se dibuja con este zoom
+        assert!(render_line_segments(&mut map).is_empty());
+
+        map.set_route(1, vec![3, 1, 2], RouteStyle::default());
+        let segments = render_line_segments(&mut map);
+        assert_eq!(segments.len(), 2);
+        let center = map.map_area.center();
+        assert_eq!(segments[0], [center - vec2(10.0, 10.0), center]);
+        assert_eq!(segments[1], [center, center + vec2(10.0, 10.0)]);
+
+        // los ids desconocidos cortan la ruta
+        map.set_route(1, vec![3, 99, 2], RouteStyle::default());
+        assert!(render_line_segments(&mut map).is_empty());
+
+        assert!(map.remove_route(1));
+        assert!(!map.remove_route(1));
+        map.set_route(2, vec![1, 2], RouteStyle::default());
+        map.clear_routes();
+        assert!(render_line_segments(&mut map).is_empty());
+    }
+
+    #[test]
+    fn routes_follow_the_loaded_lines() {
+        let mut map = Map::new();
+        map.settings.line_visible_zoom = 5.0;
+        let mut points = sample_points();
+        points[0].connections.push((7, 8));
+        points[1].connections.push((7, 8));
+        map.add_points(points);
+        // la línea compartida no llega al centro del nodo 1 y va al revés
+        map.add_lines(vec![MapSegment::new((7, 8), [10.0, 10.0], [0.0, 5.0])]);
+        map.set_pos([0.0, 0.0]);
+        map.set_route(1, vec![1, 2, 3], RouteStyle::default());
+        let segments = render_line_segments(&mut map);
+        assert_eq!(segments.len(), 2);
+        let center = map.map_area.center();
+        assert_eq!(
+            segments[0],
+            [center + vec2(0.0, 5.0), center + vec2(10.0, 10.0)]
+        );
+        // sin línea cargada entre 2 y 3 se une en recto
+        assert_eq!(
+            segments[1],
+            [center + vec2(10.0, 10.0), center - vec2(10.0, 10.0)]
+        );
+    }
+
     // ---------- notificaciones y marcadores ----------
 
     #[test]
     fn line_at_returns_closest_line_within_tolerance() {
         let mut map = Map::new();
         map.add_points(sample_points());
-        let mut lines = Vec::new();
-        lines.push(MapSegment::new((1, 2), [0.0, 0.0], [10.0, 0.0]));
-        lines.push(MapSegment::new((3, 4), [20.0, -5.0], [20.0, 5.0]));
+        let lines = vec![
+            MapSegment::new((1, 2), [0.0, 0.0], [10.0, 0.0]),
+            MapSegment::new((3, 4), [20.0, -5.0], [20.0, 5.0]),
+        ];
         map.add_lines(lines);
 
         // 1.5 units above the horizontal segment.
@@ -1355,8 +5327,7 @@ mod tests {
     fn line_at_returns_none_beyond_tolerance() {
         let mut map = Map::new();
         map.add_points(sample_points());
-        let mut lines = Vec::new();
-        lines.push(MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0]));
+        let lines = vec![MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0])];
         map.add_lines(lines);
 
         // Distance from (5,4) to the diagonal segment (0,0)-(10,10) is
@@ -1376,8 +5347,7 @@ mod tests {
     fn line_at_negative_tolerance_behaves_like_zero() {
         let mut map = Map::new();
         map.add_points(sample_points());
-        let mut lines = Vec::new();
-        lines.push(MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0]));
+        let lines = vec![MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0])];
         map.add_lines(lines);
 
         // Exact point on the segment is hit even with tolerance clamped to 0.
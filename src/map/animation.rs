@@ -1,9 +1,94 @@
 //! Built-in animation effects, used when no custom
-//! [`NodeTemplate`](crate::map::objects::NodeTemplate) is installed.
+//! [`NodeTemplate`](crate::map::objects::NodeTemplate) is installed, and the
+//! camera transitions played by [`Map::fly_to`](crate::map::Map::fly_to).
 
 use crate::map::objects::RawPoint;
 use egui::{Color32, Painter, Shape, epaint::CircleShape};
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+/// Timing curve of a camera transition started with
+/// [`Map::fly_to`](crate::map::Map::fly_to).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Easing {
+    /// Constant speed from start to end.
+    Linear,
+    /// Starts slowly and accelerates towards the end.
+    EaseIn,
+    /// Starts fast and decelerates towards the end.
+    EaseOut,
+    /// Accelerates during the first half and decelerates during the second.
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps the elapsed fraction `t` of a transition (clamped to `0..=1`) to
+    /// the fraction of the distance covered.
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+        }
+    }
+}
+
+/// An in-progress camera transition between two view positions and zoom
+/// levels.
+#[derive(Clone, Debug)]
+pub(crate) struct Flight {
+    from_pos: RawPoint,
+    to_pos: RawPoint,
+    from_zoom: f32,
+    to_zoom: f32,
+    start: Instant,
+    duration: Duration,
+    easing: Easing,
+}
+
+impl Flight {
+    pub(crate) fn new(
+        from: (RawPoint, f32),
+        to: (RawPoint, f32),
+        start: Instant,
+        duration: Duration,
+        easing: Easing,
+    ) -> Self {
+        Self {
+            from_pos: from.0,
+            to_pos: to.0,
+            from_zoom: from.1,
+            to_zoom: to.1,
+            start,
+            duration,
+            easing,
+        }
+    }
+
+    /// Returns the view position and zoom at `now`, and whether the
+    /// transition has reached its destination.
+    ///
+    /// The zoom is interpolated geometrically so zooming in and out feel
+    /// equally fast.
+    pub(crate) fn at(&self, now: Instant) -> (RawPoint, f32, bool) {
+        let elapsed = now.saturating_duration_since(self.start).as_secs_f32();
+        let total = self.duration.as_secs_f32();
+        if elapsed >= total {
+            return (self.to_pos, self.to_zoom, true);
+        }
+        let t = self.easing.apply(elapsed / total);
+        let pos = self.from_pos + (self.to_pos - self.from_pos) * t;
+        let zoom = self.from_zoom * (self.to_zoom / self.from_zoom).powf(t);
+        (pos, zoom, false)
+    }
+}
 
 /// Factory for the default node notification animation.
 pub(crate) struct Animation {}
@@ -84,4 +169,43 @@ mod tests {
         );
         assert!(!result);
     }
+
+    #[test]
+    fn easing_curves_start_and_end_at_bounds() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(1.0), 1.0);
+            // fuera de rango se recorta
+            assert_eq!(easing.apply(2.0), 1.0);
+        }
+        assert!(Easing::EaseIn.apply(0.5) < 0.5);
+        assert!(Easing::EaseOut.apply(0.5) > 0.5);
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+    }
+
+    #[test]
+    fn flight_interpolates_and_finishes() {
+        let start = Instant::now();
+        let flight = Flight::new(
+            (RawPoint::from([0.0, 0.0]), 1.0),
+            (RawPoint::from([100.0, 50.0]), 4.0),
+            start,
+            Duration::from_secs(1),
+            Easing::Linear,
+        );
+        let (pos, zoom, done) = flight.at(start + Duration::from_millis(500));
+        assert!(!done);
+        assert_eq!(pos.components, [50.0, 25.0]);
+        assert!((zoom - 2.0).abs() < 1e-4);
+
+        let (pos, zoom, done) = flight.at(start + Duration::from_secs(2));
+        assert!(done);
+        assert_eq!(pos.components, [100.0, 50.0]);
+        assert_eq!(zoom, 4.0);
+    }
 }
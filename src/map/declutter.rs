@@ -0,0 +1,175 @@
+//! Label placement for [`MapSettings::declutter_labels`](crate::map::objects::MapSettings::declutter_labels).
+//!
+//! Every label that wants to be drawn this frame offers one or more candidate
+//! screen rectangles. Labels are placed by decreasing priority, each one at
+//! its first candidate that does not overlap an already placed label; labels
+//! with no free candidate are dropped. Placed rectangles are kept in an
+//! R-tree, so each test only looks at the labels nearby.
+
+use egui::{Align, Align2, Pos2, Rect, Vec2, vec2};
+use rstar::primitives::Rectangle;
+use rstar::{AABB, RTree};
+use std::collections::{HashMap, HashSet};
+
+/// Anchors tried around a node for its name, in order of preference. The
+/// first one is where names are drawn when decluttering is off.
+pub(crate) const NODE_NAME_ANCHORS: [Align2; 6] = [
+    Align2::LEFT_BOTTOM,
+    Align2::RIGHT_BOTTOM,
+    Align2::LEFT_TOP,
+    Align2::RIGHT_TOP,
+    Align2::LEFT_CENTER,
+    Align2::RIGHT_CENTER,
+];
+
+/// Identifies a label competing for room.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum LabelKey {
+    /// Index into the labels given to [`Map::add_labels`](crate::map::Map::add_labels).
+    Map(usize),
+    /// Id of the node whose name is drawn.
+    Node(usize),
+}
+
+/// A label that wants to be drawn, with the rectangles it may occupy in
+/// order of preference.
+pub(crate) struct LabelRequest {
+    pub key: LabelKey,
+    pub priority: i32,
+    pub candidates: Vec<Rect>,
+}
+
+/// The labels kept by [`place`], with the candidate chosen for each.
+#[derive(Debug, Default)]
+pub(crate) struct LabelPlacement {
+    pub map_labels: HashSet<usize>,
+    pub node_names: HashMap<usize, Align2>,
+}
+
+/// Returns where the name of a node at `node` is drawn for `anchor`, leaving
+/// `gap` screen points between both.
+pub(crate) fn node_name_position(node: Pos2, anchor: Align2, gap: f32) -> Pos2 {
+    let offset = |align: Align| match align {
+        Align::Min => gap,
+        Align::Center => 0.0,
+        Align::Max => -gap,
+    };
+    node + vec2(offset(anchor.x()), offset(anchor.y()))
+}
+
+/// Returns the axis-aligned bounds of `rect` rotated clockwise by `angle`
+/// radians around `pivot`.
+pub(crate) fn rotated_bounds(rect: Rect, pivot: Pos2, angle: f32) -> Rect {
+    if angle == 0.0 {
+        return rect;
+    }
+    let rotation = egui::emath::Rot2::from_angle(angle);
+    Rect::from_points(
+        &[
+            rect.left_top(),
+            rect.right_top(),
+            rect.left_bottom(),
+            rect.right_bottom(),
+        ]
+        .map(|corner| pivot + rotation * (corner - pivot)),
+    )
+}
+
+/// Picks a free candidate for as many labels as possible, higher priorities
+/// first; ties keep the order of `requests`. Returns the index of the chosen
+/// candidate of every placed label.
+pub(crate) fn place(mut requests: Vec<LabelRequest>) -> HashMap<LabelKey, usize> {
+    let _span = tracing::info_span!("declutter_place").entered();
+    requests.sort_by_key(|request| std::cmp::Reverse(request.priority));
+    let mut placed: RTree<Rectangle<[f32; 2]>> = RTree::new();
+    let mut chosen = HashMap::new();
+    for request in requests {
+        let free = request.candidates.iter().position(|rect| {
+            // touching edges are not an overlap
+            let inner = rect.shrink2(Vec2::splat(0.5).min(rect.size() / 2.0));
+            placed
+                .locate_in_envelope_intersecting(AABB::from_corners(
+                    inner.min.into(),
+                    inner.max.into(),
+                ))
+                .next()
+                .is_none()
+        });
+        if let Some(index) = free {
+            let rect = request.candidates[index];
+            placed.insert(Rectangle::from_corners(rect.min.into(), rect.max.into()));
+            chosen.insert(request.key, index);
+        }
+    }
+    chosen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(key: LabelKey, priority: i32, candidates: &[Rect]) -> LabelRequest {
+        LabelRequest {
+            key,
+            priority,
+            candidates: candidates.to_vec(),
+        }
+    }
+
+    fn square(x: f32, y: f32) -> Rect {
+        Rect::from_min_size(Pos2::new(x, y), vec2(10.0, 10.0))
+    }
+
+    #[test]
+    fn higher_priority_wins_the_spot() {
+        let chosen = place(vec![
+            request(LabelKey::Node(1), 0, &[square(0.0, 0.0)]),
+            request(LabelKey::Map(0), 5, &[square(5.0, 5.0)]),
+        ]);
+        assert_eq!(chosen.get(&LabelKey::Map(0)), Some(&0));
+        assert!(!chosen.contains_key(&LabelKey::Node(1)));
+    }
+
+    #[test]
+    fn alternative_candidates_are_tried_in_order() {
+        let chosen = place(vec![
+            request(LabelKey::Node(1), 0, &[square(0.0, 0.0)]),
+            request(
+                LabelKey::Node(2),
+                0,
+                &[square(5.0, 0.0), square(5.0, 20.0), square(30.0, 30.0)],
+            ),
+            // tocar un borde no cuenta como solaparse
+            request(LabelKey::Node(3), 0, &[square(10.0, 0.0)]),
+        ]);
+        assert_eq!(chosen[&LabelKey::Node(1)], 0);
+        assert_eq!(chosen[&LabelKey::Node(2)], 1);
+        assert_eq!(chosen[&LabelKey::Node(3)], 0);
+    }
+
+    #[test]
+    fn node_names_sit_around_the_node() {
+        let node = Pos2::new(100.0, 100.0);
+        assert_eq!(
+            node_name_position(node, Align2::LEFT_BOTTOM, 3.0),
+            Pos2::new(103.0, 97.0)
+        );
+        assert_eq!(
+            node_name_position(node, Align2::RIGHT_TOP, 3.0),
+            Pos2::new(97.0, 103.0)
+        );
+        assert_eq!(
+            node_name_position(node, Align2::LEFT_CENTER, 3.0),
+            Pos2::new(103.0, 100.0)
+        );
+    }
+
+    #[test]
+    fn rotated_bounds_cover_the_rotated_rect() {
+        let rect = Rect::from_min_max(Pos2::new(0.0, -1.0), Pos2::new(10.0, 1.0));
+        let bounds = rotated_bounds(rect, Pos2::ZERO, std::f32::consts::FRAC_PI_2);
+        assert!((bounds.min.x + 1.0).abs() < 1e-4 && (bounds.max.x - 1.0).abs() < 1e-4);
+        assert!(bounds.min.y.abs() < 1e-4 && (bounds.max.y - 10.0).abs() < 1e-4);
+        assert_eq!(rotated_bounds(rect, Pos2::ZERO, 0.0), rect);
+    }
+}
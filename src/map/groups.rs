@@ -0,0 +1,262 @@
+//! Semantic zoom over the [`NodeGroup`] hierarchy.
+//!
+//! At every zoom level each group is either collapsed or open. The outermost
+//! collapsed groups stand in for all the nodes below them: [`build`] works
+//! out which nodes and lines they hide, where their shapes go and which
+//! aggregated lines join them to each other and to the nodes still shown.
+//! The result only changes when a threshold is crossed or the nodes or
+//! groups change, so [`Map`](crate::map::Map) keeps it between frames.
+
+use crate::map::objects::{MapPoint, NodeGroup};
+use crate::map::routing::connection_endpoints;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// Something drawn on the map in place of a node: the node itself or the
+/// collapsed group hiding it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Representative {
+    Node(usize),
+    Group(usize),
+}
+
+/// A collapsed group, ready to be drawn.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct GroupShape {
+    pub id: usize,
+    /// Centroid of the nodes in the group, in map coordinates.
+    pub center: [f32; 2],
+    /// Distance from the center to the farthest node, in map units.
+    pub extent: f32,
+    /// Number of nodes in the group, subgroups included.
+    pub members: usize,
+}
+
+/// What the groups collapsed at the current zoom hide and draw instead.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct GroupView {
+    /// The groups this view was built for.
+    pub collapsed: BTreeSet<usize>,
+    /// One shape per collapsed group holding at least one node, by id.
+    pub shapes: Vec<GroupShape>,
+    /// Nodes drawn as part of a collapsed group.
+    pub hidden_nodes: HashSet<usize>,
+    /// Lines with an endpoint in a collapsed group.
+    pub hidden_segments: HashSet<(usize, usize)>,
+    /// Lines standing in for the hidden ones, in map coordinates; at most one
+    /// between any two groups or group and node.
+    pub edges: Vec<[[f32; 2]; 2]>,
+    /// Shown nodes at the end of one of `edges`.
+    pub edge_nodes: HashSet<usize>,
+}
+
+impl GroupView {
+    /// Returns `true` if adding, moving or removing `point` may change this
+    /// view: the node belongs to a group, ends an aggregated line, or is
+    /// connected to a hidden node.
+    pub fn is_affected_by(&self, point: &MapPoint) -> bool {
+        point.group.is_some()
+            || self.edge_nodes.contains(&point.id)
+            || point.connections.iter().any(|connection| {
+                self.hidden_segments.contains(connection)
+                    || self.hidden_nodes.contains(&connection.0)
+                    || self.hidden_nodes.contains(&connection.1)
+            })
+    }
+}
+
+/// Returns `group` followed by its ancestors, stopping at unknown ids and
+/// at parent cycles.
+fn lineage(groups: &HashMap<usize, NodeGroup>, group: usize) -> impl Iterator<Item = usize> + '_ {
+    std::iter::successors(Some(group), |id| {
+        groups.get(id).and_then(|group| group.parent)
+    })
+    .take_while(|id| groups.contains_key(id))
+    .take(groups.len())
+}
+
+/// Returns the groups collapsed at `zoom` that are not inside another
+/// collapsed group.
+pub(crate) fn collapsed_groups(groups: &HashMap<usize, NodeGroup>, zoom: f32) -> BTreeSet<usize> {
+    let collapses = |id: &usize| zoom < groups[id].expand_zoom;
+    groups
+        .keys()
+        .filter(|id| collapses(id) && lineage(groups, **id).skip(1).all(|id| !collapses(&id)))
+        .copied()
+        .collect()
+}
+
+/// Works out what hides behind the `collapsed` groups.
+pub(crate) fn build(
+    points: &HashMap<usize, MapPoint>,
+    groups: &HashMap<usize, NodeGroup>,
+    collapsed: BTreeSet<usize>,
+) -> GroupView {
+    let _span = tracing::info_span!("build_group_view").entered();
+    let mut view = GroupView::default();
+    if collapsed.is_empty() {
+        view.collapsed = collapsed;
+        return view;
+    }
+    let mut owner = HashMap::new();
+    let mut members: BTreeMap<usize, Vec<[f32; 2]>> = BTreeMap::new();
+    for point in points.values() {
+        if let Some(group) = point
+            .group
+            .and_then(|group| lineage(groups, group).find(|id| collapsed.contains(id)))
+        {
+            owner.insert(point.id, group);
+            members.entry(group).or_default().push(point.coords);
+        }
+    }
+    for (id, coords) in members {
+        let count = coords.len() as f32;
+        let center = coords.iter().fold([0.0, 0.0], |sum, point| {
+            [sum[0] + point[0] / count, sum[1] + point[1] / count]
+        });
+        let extent = coords
+            .iter()
+            .map(|point| (point[0] - center[0]).hypot(point[1] - center[1]))
+            .fold(0.0, f32::max);
+        view.shapes.push(GroupShape {
+            id,
+            center,
+            extent,
+            members: coords.len(),
+        });
+    }
+    let centers: HashMap<usize, [f32; 2]> = view
+        .shapes
+        .iter()
+        .map(|shape| (shape.id, shape.center))
+        .collect();
+    let represent = |node: usize| match owner.get(&node) {
+        Some(group) => Representative::Group(*group),
+        None => Representative::Node(node),
+    };
+    let position = |representative: Representative| match representative {
+        Representative::Node(id) => points[&id].coords,
+        Representative::Group(id) => centers[&id],
+    };
+    let mut edges = BTreeSet::new();
+    for (connection, (a, b)) in connection_endpoints(points) {
+        let (a, b) = (represent(a), represent(b));
+        if matches!((a, b), (Representative::Node(_), Representative::Node(_))) {
+            continue;
+        }
+        view.hidden_segments.insert(connection);
+        if a != b {
+            edges.insert((a.min(b), a.max(b)));
+        }
+    }
+    // nodes sort before groups, so a shown node always comes first
+    for (a, _) in &edges {
+        if let Representative::Node(id) = a {
+            view.edge_nodes.insert(*id);
+        }
+    }
+    view.edges = edges
+        .into_iter()
+        .map(|(a, b)| [position(a), position(b)])
+        .collect();
+    view.hidden_nodes = owner.into_keys().collect();
+    view.collapsed = collapsed;
+    view
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups() -> HashMap<usize, NodeGroup> {
+        HashMap::from([
+            (1, NodeGroup::new("Region", 0.5)),
+            (
+                10,
+                NodeGroup {
+                    parent: Some(1),
+                    ..NodeGroup::new("North", 1.0)
+                },
+            ),
+            (
+                11,
+                NodeGroup {
+                    parent: Some(1),
+                    ..NodeGroup::new("South", 1.0)
+                },
+            ),
+        ])
+    }
+
+    fn point(id: usize, coords: [f32; 2], group: Option<usize>, links: &[usize]) -> MapPoint {
+        let mut point = MapPoint::new(id, coords);
+        point.group = group;
+        point.connections = links
+            .iter()
+            .map(|other| (id.min(*other), id.max(*other)))
+            .collect();
+        point
+    }
+
+    fn points() -> HashMap<usize, MapPoint> {
+        [
+            point(100, [0.0, 0.0], Some(10), &[101, 110]),
+            point(101, [10.0, 0.0], Some(10), &[100]),
+            point(110, [0.0, 20.0], Some(11), &[100, 200]),
+            point(200, [50.0, 50.0], None, &[110]),
+        ]
+        .into_iter()
+        .map(|point| (point.id, point))
+        .collect()
+    }
+
+    #[test]
+    fn only_outermost_collapsed_groups_count() {
+        let groups = groups();
+        assert_eq!(collapsed_groups(&groups, 0.2), BTreeSet::from([1]));
+        assert_eq!(collapsed_groups(&groups, 0.7), BTreeSet::from([10, 11]));
+        assert!(collapsed_groups(&groups, 1.0).is_empty());
+    }
+
+    #[test]
+    fn parent_cycles_do_not_hang() {
+        let mut groups = groups();
+        groups.get_mut(&1).unwrap().parent = Some(11);
+        // dentro de un ciclo todos tienen un antecesor plegado
+        assert!(collapsed_groups(&groups, 0.2).is_empty());
+        assert_eq!(lineage(&groups, 10).count(), 3);
+    }
+
+    #[test]
+    fn collapsed_groups_hide_members_and_merge_lines() {
+        let view = build(&points(), &groups(), BTreeSet::from([10, 11]));
+        assert_eq!(view.hidden_nodes, HashSet::from([100, 101, 110]));
+        assert_eq!(view.shapes.len(), 2);
+        assert_eq!(view.shapes[0].center, [5.0, 0.0]);
+        assert_eq!(view.shapes[0].extent, 5.0);
+        assert_eq!(view.shapes[0].members, 2);
+        // la línea interna de North desaparece sin sustituto
+        assert_eq!(view.hidden_segments.len(), 3);
+        assert_eq!(
+            view.edges,
+            vec![[[50.0, 50.0], [0.0, 20.0]], [[5.0, 0.0], [0.0, 20.0]]]
+        );
+        assert_eq!(view.edge_nodes, HashSet::from([200]));
+        // solo afectan a la vista los nodos de grupos o unidos a ellos
+        let points = points();
+        assert!(view.is_affected_by(&points[&101]));
+        assert!(view.is_affected_by(&points[&200]));
+        let mut linked = MapPoint::new(300, [0.0, 0.0]);
+        assert!(!view.is_affected_by(&linked));
+        linked.connections.push((100, 300));
+        assert!(view.is_affected_by(&linked));
+    }
+
+    #[test]
+    fn outer_group_swallows_subgroups() {
+        let view = build(&points(), &groups(), BTreeSet::from([1]));
+        assert_eq!(view.shapes.len(), 1);
+        assert_eq!(view.shapes[0].members, 3);
+        assert_eq!(view.edges.len(), 1);
+        assert!(!view.hidden_nodes.contains(&200));
+    }
+}
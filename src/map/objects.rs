@@ -1,14 +1,23 @@
 //! Data types consumed by the [`Map`](super::Map) widget.
 //!
 //! This module contains the geometry primitives ([`RawPoint`], [`RawLine`]),
-//! the map content types ([`MapPoint`], [`MapSegment`], [`MapLabel`]) and the
-//! customization points of the widget: [`MapSettings`], [`MapStyle`],
-//! [`VisibilitySetting`], [`ContextMenuManager`] and [`NodeTemplate`].
-
-use egui::{Align2, Color32, FontFamily, FontId, Pos2, Stroke, Ui};
+//! the map content types ([`MapPoint`], [`NodeData`], [`NodeLayer`], [`NodeGroup`],
+//! [`MapSegment`], [`MapLabel`], [`RouteStyle`], [`NodeCluster`]), the
+//! interaction results returned by [`Map::show`](super::Map::show)
+//! ([`MapResponse`], [`MapEvent`]) and the customization points of the
+//! widget: [`MapSettings`], [`MapStyle`], [`VisibilitySetting`],
+//! [`SelectionMode`], [`DragBehavior`], [`SelectionShape`], [`ScrollAction`],
+//! [`SearchBox`], [`ContextMenuManager`], [`NodeTemplate`],
+//! [`ClusterTemplate`] and [`MapLayer`] (with its [`MapTransform`]).
+
+use egui::{Align2, Color32, FontFamily, FontId, Painter, Pos2, Rect, Response, Stroke, Ui};
 use rstar::AABB;
+use std::any::Any;
 use std::convert::{From, Into};
+use std::fmt;
 use std::ops::{Add, Div, DivAssign, Mul, MulAssign, Sub};
+use std::rc::Rc;
+use std::sync::Arc;
 use std::time::Instant;
 
 /// A point (or vector) in 2D map coordinates.
@@ -469,6 +478,8 @@ pub struct MapStyle {
     pub background_color: Color32,
     /// Color used for notification pulse animations.
     pub alert_color: Color32,
+    /// Color of the ring drawn around selected nodes.
+    pub selection_color: Color32,
 }
 
 impl MapStyle {
@@ -482,6 +493,7 @@ impl MapStyle {
             font: None,
             background_color: Color32::TRANSPARENT,
             alert_color: Color32::TRANSPARENT,
+            selection_color: Color32::TRANSPARENT,
         }
     }
 }
@@ -573,15 +585,58 @@ impl Div<f64> for MapStyle {
     }
 }
 
-/// A free-floating text label drawn on the map.
+/// A free-floating text label drawn on the map, such as a region name.
 ///
 /// Labels are installed with [`Map::add_labels`](super::Map::add_labels).
+/// They are anchored to a position in map coordinates, so they pan and zoom
+/// with the nodes, and are only drawn while the zoom level is within their
+/// own range.
+///
+/// Labels used to take a `center` in screen coordinates, which stayed put
+/// while the map moved. A label still placed by screen position can be moved
+/// over with [`Map::screen_to_map`](super::Map::screen_to_map):
+/// `MapLabel::at(text, map.screen_to_map(center))`.
+///
+/// # Examples
+///
+/// ```
+/// use egui::{Align2, Color32, FontId};
+/// use egui_map::map::objects::MapLabel;
+///
+/// let region = MapLabel {
+///     max_zoom: Some(0.3),
+///     font: Some(FontId::proportional(22.0)),
+///     color: Some(Color32::GOLD),
+///     anchor: Align2::CENTER_BOTTOM,
+///     priority: 10,
+///     ..MapLabel::at("The Forge", [120.0, -40.0])
+/// };
+/// assert!(region.is_shown(0.1, 0.2));
+/// assert!(!region.is_shown(0.5, 0.2));
+/// ```
 #[derive(Clone, Debug)]
 pub struct MapLabel {
     /// The text to display.
     pub text: String,
-    /// The position of the label's center.
-    pub center: Pos2,
+    /// Map coordinates the label is anchored at.
+    pub position: [f32; 2],
+    /// Zoom level from which the label is drawn.
+    pub min_zoom: f32,
+    /// Zoom level from which the label is hidden again; `None` hides it from
+    /// [`MapSettings::line_visible_zoom`] on.
+    pub max_zoom: Option<f32>,
+    /// Font of the text, in screen points. `None` uses a proportional font
+    /// that grows and shrinks with the zoom.
+    pub font: Option<FontId>,
+    /// Color of the text; `None` uses the text color of the egui theme.
+    pub color: Option<Color32>,
+    /// Which point of the text sits at [`position`](Self::position).
+    pub anchor: Align2,
+    /// Clockwise rotation around the anchor, in radians.
+    pub rotation: f32,
+    /// Labels with a higher priority are drawn above the others and, with
+    /// [`MapSettings::declutter_labels`], keep their room first.
+    pub priority: i32,
 }
 
 impl Default for MapLabel {
@@ -591,13 +646,37 @@ impl Default for MapLabel {
 }
 
 impl MapLabel {
-    /// Creates an empty label centered at the origin.
+    /// Creates an empty label centered at the origin, shown below
+    /// [`MapSettings::line_visible_zoom`].
     pub fn new() -> Self {
         MapLabel {
             text: String::new(),
-            center: Pos2::new(0.00, 0.00),
+            position: [0.0, 0.0],
+            min_zoom: 0.0,
+            max_zoom: None,
+            font: None,
+            color: None,
+            anchor: Align2::CENTER_CENTER,
+            rotation: 0.0,
+            priority: 0,
         }
     }
+
+    /// Creates a label with the default style, centered at `position` in map
+    /// coordinates.
+    pub fn at(text: impl Into<String>, position: [f32; 2]) -> Self {
+        MapLabel {
+            text: text.into(),
+            position,
+            ..MapLabel::new()
+        }
+    }
+
+    /// Returns `true` if the label is drawn at `zoom`, given the map's
+    /// [`MapSettings::line_visible_zoom`].
+    pub fn is_shown(&self, zoom: f32, line_visible_zoom: f32) -> bool {
+        zoom >= self.min_zoom && zoom < self.max_zoom.unwrap_or(line_visible_zoom)
+    }
 }
 
 /// A connection line between two points on the map, ready to be stored in an
@@ -671,7 +750,8 @@ impl rstar::RTreeObject for MapSegment {
 ///   for a bare coordinate with no entity behind it (e.g. a bounding-box
 ///   corner); every `MapPoint` loaded into the widget represents a real,
 ///   placed node whose id is used directly as the point-set `HashMap` key
-///   and the kd-tree payload (see [`Map::add_hashmap_points`]), so an
+///   and the kd-tree payload (see
+///   [`Map::add_hashmap_points`](super::Map::add_hashmap_points)), so an
 ///   optional id would just push an `.unwrap()` (or a silently dropped
 ///   node) into those call sites with no caller ever passing `None`.
 ///
@@ -696,6 +776,25 @@ pub struct MapPoint {
     /// visibility, so a line is drawn whenever its bounding box intersects
     /// the viewport.
     pub connections: Vec<(usize, usize)>,
+    /// Application data attached to the node; empty by default.
+    ///
+    /// Read it back with [`MapPoint::data`], e.g. from
+    /// [`NodeTemplate::node_ui`] or when handling a [`MapEvent`], instead of
+    /// keeping a side table keyed by node id.
+    pub data: NodeData,
+    /// Name of the [`NodeLayer`] the node belongs to, or `None` for the
+    /// default layer.
+    ///
+    /// Nodes naming a layer that was never registered with
+    /// [`Map::add_node_layer`](super::Map::add_node_layer) are drawn as part
+    /// of the default layer.
+    pub layer: Option<String>,
+    /// Id of the innermost [`NodeGroup`] the node belongs to, if any.
+    ///
+    /// While that group, or any group enclosing it, is collapsed the node is
+    /// drawn as part of the group (see
+    /// [`Map::add_node_group`](super::Map::add_node_group)).
+    pub group: Option<usize>,
 }
 
 impl MapPoint {
@@ -706,9 +805,42 @@ impl MapPoint {
             id,
             connections: Vec::new(),
             name: None,
+            data: NodeData::default(),
+            layer: None,
+            group: None,
         }
     }
 
+    /// Returns the name of the layer the node belongs to, if any.
+    pub fn get_layer(&self) -> Option<&str> {
+        self.layer.as_deref()
+    }
+
+    /// Moves the node to the named layer.
+    pub fn set_layer(&mut self, value: String) {
+        self.layer = Some(value);
+    }
+
+    /// Returns the id of the group the node belongs to, if any.
+    pub fn get_group(&self) -> Option<usize> {
+        self.group
+    }
+
+    /// Moves the node into the group with the given id.
+    pub fn set_group(&mut self, value: usize) {
+        self.group = Some(value);
+    }
+
+    /// Returns the attached data if it is of type `T`.
+    pub fn data<T: Any>(&self) -> Option<&T> {
+        self.data.get()
+    }
+
+    /// Attaches `value` to the node, replacing any previous data.
+    pub fn set_data<T: Any + Send + Sync>(&mut self, value: T) {
+        self.data = NodeData::new(value);
+    }
+
     /// Returns the node identifier.
     pub fn get_id(&self) -> usize {
         self.id
@@ -725,6 +857,68 @@ impl MapPoint {
     }
 }
 
+/// A type-erased value attached to a [`MapPoint`].
+///
+/// The value is reference counted, so cloning a node (or the whole
+/// [`Map`](super::Map)) shares it instead of copying it. It must be `Send`
+/// and `Sync`, so nodes can still be built on a worker thread. Two
+/// `NodeData` are equal when they are both empty or point to the same value.
+///
+/// # Examples
+///
+/// ```
+/// use egui_map::map::objects::MapPoint;
+///
+/// struct System {
+///     security: f32,
+/// }
+///
+/// let mut point = MapPoint::new(1, [0.0, 0.0]);
+/// point.set_data(System { security: 0.5 });
+///
+/// assert_eq!(point.data::<System>().map(|s| s.security), Some(0.5));
+/// assert!(point.data::<String>().is_none());
+/// ```
+#[derive(Clone, Default)]
+pub struct NodeData(Option<Arc<dyn Any + Send + Sync>>);
+
+impl NodeData {
+    /// Wraps `value`.
+    pub fn new<T: Any + Send + Sync>(value: T) -> Self {
+        Self(Some(Arc::new(value)))
+    }
+
+    /// Returns the value if it is of type `T`.
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        self.0.as_ref()?.downcast_ref()
+    }
+
+    /// Returns `true` if no value is attached.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+}
+
+impl fmt::Debug for NodeData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            f.write_str("NodeData(None)")
+        } else {
+            f.write_str("NodeData(..)")
+        }
+    }
+}
+
+impl PartialEq for NodeData {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (None, None) => true,
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
 impl From<std::collections::hash_map::OccupiedEntry<'_, usize, MapPoint>> for MapPoint {
     fn from(value: std::collections::hash_map::OccupiedEntry<'_, usize, MapPoint>) -> Self {
         let k = value.get();
@@ -732,6 +926,95 @@ impl From<std::collections::hash_map::OccupiedEntry<'_, usize, MapPoint>> for Ma
     }
 }
 
+/// An interaction detected by the [`Map`](super::Map) widget during a frame.
+///
+/// Events are collected by [`Map::show`](super::Map::show) and returned in
+/// [`MapResponse::events`], in the order they were detected. Nodes and
+/// segments are hit-tested against the same spatial indexes the widget uses
+/// for painting, within [`MapSettings::pick_radius`] of the pointer.
+///
+/// New kinds of events may be added in minor releases, so matches on it need
+/// a wildcard arm.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum MapEvent {
+    /// A node was clicked with the primary button.
+    NodeClicked {
+        /// Id of the clicked node.
+        id: usize,
+    },
+    /// A node was double-clicked. The second click of a double click is also
+    /// reported as a [`MapEvent::NodeClicked`] right before this event.
+    NodeDoubleClicked {
+        /// Id of the double-clicked node.
+        id: usize,
+    },
+    /// A connection line was clicked, away from any node. Lines can only be
+    /// clicked while they are visible (see [`MapSettings::line_visible_zoom`]).
+    SegmentClicked {
+        /// Id of the clicked line, as in [`MapSegment::id`].
+        id: (usize, usize),
+    },
+    /// An empty area of the map was clicked.
+    BackgroundClicked {
+        /// The clicked position, in map coordinates.
+        position: [f32; 2],
+    },
+    /// The pointer started hovering a node.
+    NodeHoverEntered {
+        /// Id of the hovered node.
+        id: usize,
+    },
+    /// The pointer stopped hovering a node.
+    NodeHoverLeft {
+        /// Id of the node that is no longer hovered.
+        id: usize,
+    },
+    /// A cluster of nodes was clicked (see [`MapSettings::cluster_radius`]).
+    /// The view has already been zoomed to fit its members.
+    ClusterClicked {
+        /// Ids of the nodes in the cluster.
+        members: Vec<usize>,
+    },
+    /// A collapsed [`NodeGroup`] was clicked. The view has already been
+    /// centered on it and zoomed in to its
+    /// [`expand_zoom`](NodeGroup::expand_zoom), so it opens up.
+    GroupClicked {
+        /// Id of the clicked group.
+        id: usize,
+    },
+    /// The user changed the node selection; read the new one with
+    /// [`Map::selected_nodes`](super::Map::selected_nodes). Not emitted for
+    /// changes made through the selection methods of the widget.
+    SelectionChanged,
+}
+
+/// The result of rendering a [`Map`](super::Map) with
+/// [`Map::show`](super::Map::show).
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn example(ui: &mut egui::Ui, map: &mut egui_map::map::Map) {
+/// use egui_map::map::objects::MapEvent;
+///
+/// let map_response = map.show(ui);
+/// for event in &map_response.events {
+///     if let MapEvent::NodeClicked { id } = event {
+///         println!("clicked node {id}");
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct MapResponse {
+    /// The response of the map canvas, as returned by the
+    /// [`egui::Widget`] implementation.
+    pub response: Response,
+    /// The interactions detected during this frame.
+    pub events: Vec<MapEvent>,
+}
+
 #[derive(Clone)]
 pub(crate) struct MapBounds {
     pub min: RawPoint,
@@ -757,6 +1040,38 @@ impl Default for MapBounds {
     }
 }
 
+/// Appearance of a route drawn with [`Map::set_route`](super::Map::set_route).
+///
+/// Unlike the node shapes, the waypoint badges keep the same screen size at
+/// every zoom level so their numbers stay readable.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteStyle {
+    /// Stroke of the route lines.
+    pub stroke: Stroke,
+    /// Fill color of the waypoint badges.
+    pub waypoint_color: Color32,
+    /// Color of the waypoint numbers.
+    pub number_color: Color32,
+    /// Radius of the waypoint badges, in screen points.
+    pub waypoint_radius: f32,
+    /// Whether the waypoints are numbered from 1; if `false` no badges are
+    /// drawn.
+    pub numbered: bool,
+}
+
+impl Default for RouteStyle {
+    /// An orange route with numbered waypoints.
+    fn default() -> Self {
+        Self {
+            stroke: Stroke::new(3.0, Color32::ORANGE),
+            waypoint_color: Color32::ORANGE,
+            number_color: Color32::BLACK,
+            waypoint_radius: 8.0,
+            numbered: true,
+        }
+    }
+}
+
 pub(crate) struct TextSettings {
     pub position: RawPoint,
     pub anchor: Align2,
@@ -786,14 +1101,53 @@ pub struct MapSettings {
     pub label_visible_zoom: f32,
     /// Controls when node names are displayed.
     pub node_text_visibility: VisibilitySetting,
+    /// Nodes closer than this many screen points to each other are drawn as
+    /// a single cluster showing how many they are; `0.0` disables clustering.
+    /// A chain of such nodes makes up a single cluster.
+    ///
+    /// Clusters split as you zoom in, and nothing is clustered at
+    /// [`max_zoom`](Self::max_zoom). Clicking a cluster zooms to fit its
+    /// members and reports a [`MapEvent::ClusterClicked`].
+    pub cluster_radius: f32,
+    /// Whether overlapping labels are thinned out.
+    ///
+    /// When enabled, node names try other spots around their node and then
+    /// make way for higher-priority labels, as do the labels installed with
+    /// [`Map::add_labels`](super::Map::add_labels) (see
+    /// [`MapLabel::priority`]). The hovered node's name goes first, then the
+    /// selected nodes' names; other names have priority `0`.
+    pub declutter_labels: bool,
+    /// Distance from a node or line within which the pointer hits it, in
+    /// unscaled points: like the node shapes, the area grows and shrinks with
+    /// the zoom factor.
+    pub pick_radius: f32,
+    /// Controls how clicking on nodes changes the selection.
+    pub selection_mode: SelectionMode,
+    /// What dragging on the map does; holding `Shift` while dragging
+    /// performs the other gesture.
+    pub drag_behavior: DragBehavior,
+    /// Shape drawn by the selection drag gesture.
+    pub selection_shape: SelectionShape,
+    /// What scrolling (mouse wheel or two-finger trackpad scroll) does.
+    /// Pinch gestures always zoom.
+    pub scroll_action: ScrollAction,
+    /// Whether a long press on touch screens opens the context menu
+    /// installed with
+    /// [`Map::set_context_manager`](super::Map::set_context_manager).
+    pub long_press_menu: bool,
+    /// Whether a node search box is drawn in the top-left corner, and how it
+    /// brings the chosen node into view.
+    pub search_box: SearchBox,
     /// Per-theme styles; index `0` is used in light mode, index `1` in dark
     /// mode.
     pub styles: Vec<MapStyle>,
 }
 
 impl MapSettings {
-    /// Creates settings with all zoom thresholds set to `0.0` and a single
-    /// transparent style.
+    /// Creates settings with all zoom thresholds set to `0.0`, a pick radius
+    /// of `8.0`, multiple selection, drag to pan, scroll to zoom, long press
+    /// for the context menu, no clustering, no label decluttering, no search
+    /// box and a single transparent style.
     ///
     /// Prefer [`MapSettings::default()`] unless you really need to build the
     /// configuration from scratch.
@@ -804,6 +1158,15 @@ impl MapSettings {
             line_visible_zoom: 0.0,
             label_visible_zoom: 0.0,
             node_text_visibility: VisibilitySetting::Always,
+            cluster_radius: 0.0,
+            declutter_labels: false,
+            pick_radius: 8.0,
+            selection_mode: SelectionMode::Multiple,
+            drag_behavior: DragBehavior::Pan,
+            selection_shape: SelectionShape::Rectangle,
+            scroll_action: ScrollAction::Zoom,
+            long_press_menu: true,
+            search_box: SearchBox::Hidden,
             styles: vec![MapStyle::new()],
         }
     }
@@ -811,7 +1174,10 @@ impl MapSettings {
 
 impl Default for MapSettings {
     /// Returns the default configuration: zoom from `0.1` to `2.0`, connection
-    /// lines visible above `0.2`, node names above `0.58`, and built-in light
+    /// lines visible above `0.2`, node names above `0.58`, a pick radius of
+    /// `8.0`, multiple selection, drag to pan (`Shift`-drag to select with a
+    /// rectangle), scroll to zoom, long press for the context menu, no
+    /// clustering, no label decluttering, no search box, and built-in light
     /// and dark themes.
     fn default() -> Self {
         let mut obj = MapSettings {
@@ -820,6 +1186,15 @@ impl Default for MapSettings {
             line_visible_zoom: 0.2,
             label_visible_zoom: 0.58,
             node_text_visibility: VisibilitySetting::Always,
+            cluster_radius: 0.0,
+            declutter_labels: false,
+            pick_radius: 8.0,
+            selection_mode: SelectionMode::Multiple,
+            drag_behavior: DragBehavior::Pan,
+            selection_shape: SelectionShape::Rectangle,
+            scroll_action: ScrollAction::Zoom,
+            long_press_menu: true,
+            search_box: SearchBox::Hidden,
             styles: Vec::new(),
         };
 
@@ -838,6 +1213,7 @@ impl Default for MapSettings {
             font: Some(FontId::new(12.00, FontFamily::Proportional)),
             background_color: Color32::WHITE,
             alert_color: Color32::from_rgb(246, 30, 131),
+            selection_color: Color32::from_rgb(30, 110, 230),
         });
 
         // Dark Theme
@@ -855,6 +1231,7 @@ impl Default for MapSettings {
             font: Some(FontId::new(12.00, FontFamily::Proportional)),
             background_color: Color32::DARK_GRAY,
             alert_color: Color32::from_rgb(128, 12, 67),
+            selection_color: Color32::LIGHT_BLUE,
         });
         obj
     }
@@ -871,6 +1248,76 @@ pub enum VisibilitySetting {
     Always,
 }
 
+/// Controls how clicking on nodes changes the selection of a
+/// [`Map`](super::Map).
+#[derive(Clone, Debug, PartialEq)]
+pub enum SelectionMode {
+    /// Clicks never change the selection; it can still be set through
+    /// [`Map::set_selection`](super::Map::set_selection) and friends.
+    Disabled,
+    /// A click selects the clicked node only; clicking the background clears
+    /// the selection.
+    Single,
+    /// Like [`SelectionMode::Single`], but holding `Ctrl`, `Shift` (or `Cmd`
+    /// on macOS) while clicking toggles the node in the current selection.
+    Multiple,
+}
+
+/// The gesture performed by dragging on a [`Map`](super::Map).
+///
+/// Holding `Shift` when the drag starts performs the other gesture, so with
+/// the default [`DragBehavior::Pan`] a `Shift`-drag selects nodes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DragBehavior {
+    /// Dragging moves the view.
+    Pan,
+    /// Dragging draws a [`SelectionShape`] and selects every node inside it
+    /// when released; holding `Ctrl` (or `Cmd` on macOS) on release adds
+    /// them to the current selection instead of replacing it. Only available
+    /// with [`SelectionMode::Multiple`]; otherwise the drag pans.
+    Select,
+}
+
+/// The shape drawn by the selection drag gesture (see [`DragBehavior`]).
+#[derive(Clone, Debug, PartialEq)]
+pub enum SelectionShape {
+    /// A rectangle spanning from the drag origin to the pointer.
+    Rectangle,
+    /// A freeform outline following the pointer, closed back to the drag
+    /// origin.
+    Lasso,
+}
+
+/// What scrolling does on a [`Map`](super::Map).
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScrollAction {
+    /// Scrolling zooms around the pointer; hold `Ctrl` (or `Cmd` on macOS)
+    /// to zoom faster. Best suited for mouse wheels.
+    Zoom,
+    /// Scrolling pans the view and `Ctrl`-scroll (or `Cmd`-scroll on macOS)
+    /// zooms. Best suited for trackpads, where two-finger scrolling is the
+    /// natural way to move around.
+    Pan,
+}
+
+/// Whether a [`Map`](super::Map) draws its node search box, and what picking
+/// a result does.
+///
+/// The box lists the best matches of
+/// [`Map::search`](super::Map::search) while typing; `Enter` picks the first
+/// one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SearchBox {
+    /// No search box is drawn.
+    Hidden,
+    /// Picking a result centers the view on the node at once, as
+    /// [`Map::set_pos_from_nodeid`](super::Map::set_pos_from_nodeid) does.
+    Jump,
+    /// Picking a result flies to the node over the given time, as
+    /// [`Map::fly_to_node`](super::Map::fly_to_node) does.
+    Fly(std::time::Duration),
+}
+
 /// Provides the contents of the widget's right-click context menu.
 ///
 /// Install an implementation with
@@ -1003,6 +1450,16 @@ pub trait NodeTemplate {
     /// [`MapSettings::node_text_visibility`] is [`VisibilitySetting::Hover`].
     fn selection_ui(&self, ui: &mut Ui, _viewport_position: Pos2, _zoom: f32);
 
+    /// Draws the highlight over a selected node (see
+    /// [`Map::selected_nodes`](super::Map::selected_nodes)).
+    ///
+    /// Called every frame for each visible selected node, before
+    /// [`NodeTemplate::node_ui`]. Defaults to the hover highlight drawn by
+    /// [`NodeTemplate::selection_ui`].
+    fn selected_ui(&self, ui: &mut Ui, viewport_position: Pos2, zoom: f32) {
+        self.selection_ui(ui, viewport_position, zoom);
+    }
+
     /// Draws the notification effect of a node notified at `initial_time`.
     ///
     /// Called every frame for each node passed to
@@ -1028,6 +1485,244 @@ pub trait NodeTemplate {
     fn marker_ui(&self, ui: &mut Ui, _viewport_position: Pos2, _zoom: f32);
 }
 
+/// A group of nearby nodes drawn as a single glyph while zoomed out.
+///
+/// Built every frame when [`MapSettings::cluster_radius`] is set; read the
+/// clusters of the last frame with [`Map::clusters`](super::Map::clusters).
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeCluster {
+    /// Ids of the grouped nodes, in ascending order.
+    pub members: Vec<usize>,
+    /// Mean position of the members, in map coordinates.
+    pub center: [f32; 2],
+}
+
+/// Draws node clusters, replacing the default counter bubble.
+///
+/// Install an implementation with
+/// [`Map::set_cluster_template`](super::Map::set_cluster_template).
+///
+/// # Examples
+///
+/// ```
+/// use egui::{Align2, Color32, FontId, Pos2, Ui};
+/// use egui_map::map::objects::{ClusterTemplate, NodeCluster};
+///
+/// struct Squares;
+///
+/// impl ClusterTemplate for Squares {
+///     fn cluster_ui(&self, ui: &mut Ui, position: Pos2, _zoom: f32, cluster: &NodeCluster) {
+///         let rect = egui::Rect::from_center_size(position, egui::vec2(24.0, 24.0));
+///         ui.painter().rect_filled(rect, 4.0, Color32::DARK_BLUE);
+///         ui.painter().text(
+///             position,
+///             Align2::CENTER_CENTER,
+///             cluster.members.len().to_string(),
+///             FontId::monospace(12.0),
+///             Color32::WHITE,
+///         );
+///     }
+/// }
+/// ```
+pub trait ClusterTemplate {
+    /// Draws a cluster centered at `viewport_position`.
+    ///
+    /// Clicks hit the cluster within [`hit_radius`](Self::hit_radius) of
+    /// that position.
+    fn cluster_ui(&self, ui: &mut Ui, viewport_position: Pos2, zoom: f32, cluster: &NodeCluster);
+
+    /// Returns how far from its center, in screen points, a click still hits
+    /// the cluster. Defaults to the radius of the default bubble:
+    /// `10 + 3 * log2(members)`.
+    fn hit_radius(&self, _zoom: f32, cluster: &NodeCluster) -> f32 {
+        super::cluster_glyph_radius(cluster.members.len())
+    }
+}
+
+/// A level of a node hierarchy, such as a region containing constellations
+/// containing systems.
+///
+/// Groups are registered with
+/// [`Map::add_node_group`](super::Map::add_node_group) under an id; nodes
+/// join the innermost group through [`MapPoint::group`] and groups nest
+/// through [`NodeGroup::parent`]. Below its
+/// [`expand_zoom`](Self::expand_zoom) a group is collapsed: its nodes and
+/// subgroups are replaced by a single shape with the group name, and lines
+/// leading into it are merged into one line per neighbouring node or group.
+///
+/// # Examples
+///
+/// ```
+/// use egui_map::map::Map;
+/// use egui_map::map::objects::{MapPoint, NodeGroup};
+///
+/// let mut map = Map::new();
+/// map.add_node_group(1, NodeGroup::new("The Forge", 0.3));
+/// map.add_node_group(
+///     10,
+///     NodeGroup {
+///         parent: Some(1),
+///         ..NodeGroup::new("Kimotoro", 0.8)
+///     },
+/// );
+///
+/// let mut jita = MapPoint::new(100, [0.0, 0.0]);
+/// jita.set_group(10);
+/// map.add_points(vec![jita]);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeGroup {
+    /// Name drawn over the group while it is collapsed.
+    pub name: String,
+    /// Id of the enclosing group, if any.
+    pub parent: Option<usize>,
+    /// Zoom level from which the group shows its contents instead of itself.
+    pub expand_zoom: f32,
+}
+
+impl NodeGroup {
+    /// Creates a top-level group that expands at `expand_zoom`.
+    pub fn new(name: impl Into<String>, expand_zoom: f32) -> Self {
+        Self {
+            name: name.into(),
+            parent: None,
+            expand_zoom,
+        }
+    }
+}
+
+/// A named group of nodes that can be shown, hidden and ordered together.
+///
+/// Nodes join a layer through [`MapPoint::layer`]; layers are registered with
+/// [`Map::add_node_layer`](super::Map::add_node_layer). Nodes on hidden layers,
+/// or outside of the layer's zoom range, are neither painted nor picked by
+/// clicks, hover or selection gestures. Nodes without a registered layer
+/// behave as if they were on a visible layer with `z_order` 0 and no
+/// template.
+///
+/// # Examples
+///
+/// ```
+/// use egui_map::map::Map;
+/// use egui_map::map::objects::{MapPoint, NodeLayer};
+///
+/// let mut station = MapPoint::new(1, [0.0, 0.0]);
+/// station.set_layer("stations".to_string());
+///
+/// let mut map = Map::new();
+/// map.add_points(vec![station]);
+/// map.add_node_layer("stations", NodeLayer {
+///     min_zoom: 1.0,
+///     z_order: 10,
+///     ..NodeLayer::default()
+/// });
+///
+/// map.set_node_layer_visible("stations", false);
+/// ```
+#[derive(Clone)]
+pub struct NodeLayer {
+    /// Whether the layer is drawn at all.
+    pub visible: bool,
+    /// The layer is hidden while the zoom is below this value.
+    pub min_zoom: f32,
+    /// The layer is hidden while the zoom is above this value.
+    pub max_zoom: f32,
+    /// Painting order: layers with a higher value are drawn on top.
+    pub z_order: i32,
+    /// Template used for the nodes of this layer instead of the one installed
+    /// with [`Map::set_node_template`](super::Map::set_node_template).
+    pub template: Option<Rc<dyn NodeTemplate>>,
+}
+
+impl NodeLayer {
+    /// Returns `true` if the layer is drawn at the given zoom level.
+    pub fn is_shown(&self, zoom: f32) -> bool {
+        self.visible && zoom >= self.min_zoom && zoom <= self.max_zoom
+    }
+}
+
+impl Default for NodeLayer {
+    /// A visible layer at every zoom level, with `z_order` 0 and no template.
+    fn default() -> Self {
+        Self {
+            visible: true,
+            min_zoom: 0.0,
+            max_zoom: f32::INFINITY,
+            z_order: 0,
+            template: None,
+        }
+    }
+}
+
+/// The mapping between map and screen coordinates for the current frame,
+/// handed to [`MapLayer::paint`].
+///
+/// Follows the widget's coordinate model: `screen = map * zoom - origin`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MapTransform {
+    /// Current zoom factor.
+    pub zoom: f32,
+    /// Screen-space offset subtracted after scaling.
+    pub origin: RawPoint,
+}
+
+impl MapTransform {
+    /// Converts map coordinates to a screen position.
+    pub fn map_to_screen(&self, point: [f32; 2]) -> Pos2 {
+        (RawPoint::from(point) * self.zoom - self.origin).into()
+    }
+
+    /// Converts a screen position to map coordinates.
+    pub fn screen_to_map(&self, position: Pos2) -> [f32; 2] {
+        ((RawPoint::from(position) + self.origin) / self.zoom).into()
+    }
+
+    /// Converts a length in map units to screen points.
+    pub fn scale(&self, length: f32) -> f32 {
+        length * self.zoom
+    }
+}
+
+/// A custom overlay painted as part of the map, between or above the built-in
+/// content.
+///
+/// Register it with [`Map::add_map_layer`](super::Map::add_map_layer) and a
+/// z-index: layers are painted in increasing z order, interleaved with the
+/// built-in labels ([`Map::LABELS_Z`](super::Map::LABELS_Z)), lines
+/// ([`Map::LINES_Z`](super::Map::LINES_Z)), nodes
+/// ([`Map::NODES_Z`](super::Map::NODES_Z)) and markers
+/// ([`Map::MARKERS_Z`](super::Map::MARKERS_Z)). A layer sharing its z-index
+/// with built-in content is painted above it.
+///
+/// # Examples
+///
+/// ```
+/// use egui::{Color32, Painter, Rect, Stroke};
+/// use egui_map::map::objects::{MapLayer, MapTransform};
+///
+/// /// Draws a range ring of 50 map units around a point.
+/// struct RangeRing {
+///     center: [f32; 2],
+/// }
+///
+/// impl MapLayer for RangeRing {
+///     fn paint(&self, painter: &Painter, transform: &MapTransform, _visible: Rect) {
+///         painter.circle_stroke(
+///             transform.map_to_screen(self.center),
+///             transform.scale(50.0),
+///             Stroke::new(1.0, Color32::YELLOW),
+///         );
+///     }
+/// }
+/// ```
+pub trait MapLayer {
+    /// Paints one frame of the layer.
+    ///
+    /// `visible` is the part of the map shown by the widget, in map
+    /// coordinates, so the layer can skip whatever lies outside of it.
+    fn paint(&self, painter: &Painter, transform: &MapTransform, visible: Rect);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1369,6 +2064,7 @@ mod tests {
             font: Some(FontId::new(10.0, FontFamily::Proportional)),
             background_color: Color32::BLACK,
             alert_color: Color32::YELLOW,
+            selection_color: Color32::LIGHT_BLUE,
         }
     }
 
@@ -1382,6 +2078,7 @@ mod tests {
         assert_eq!(s.text_color, Color32::TRANSPARENT);
         assert_eq!(s.background_color, Color32::TRANSPARENT);
         assert_eq!(s.alert_color, Color32::TRANSPARENT);
+        assert_eq!(s.selection_color, Color32::TRANSPARENT);
     }
 
     #[test]
@@ -1462,14 +2159,35 @@ mod tests {
     fn map_label_new() {
         let l = MapLabel::new();
         assert_eq!(l.text, String::new());
-        assert_eq!(l.center, Pos2::new(0.0, 0.0));
+        assert_eq!(l.position, [0.0, 0.0]);
+        assert_eq!(l.max_zoom, None);
+        assert_eq!(l.anchor, Align2::CENTER_CENTER);
     }
 
     #[test]
     fn map_label_default_equals_new() {
         let l = MapLabel::default();
         assert_eq!(l.text, String::new());
-        assert_eq!(l.center, Pos2::new(0.0, 0.0));
+        assert_eq!(l.position, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn map_label_zoom_range() {
+        let l = MapLabel::at("Region", [1.0, 2.0]);
+        assert_eq!(l.text, "Region");
+        assert_eq!(l.position, [1.0, 2.0]);
+        // sin max_zoom se oculta a partir de line_visible_zoom
+        assert!(l.is_shown(0.1, 0.2));
+        assert!(!l.is_shown(0.2, 0.2));
+
+        let l = MapLabel {
+            min_zoom: 0.5,
+            max_zoom: Some(1.0),
+            ..l
+        };
+        assert!(!l.is_shown(0.4, 0.2));
+        assert!(l.is_shown(0.5, 0.2));
+        assert!(!l.is_shown(1.0, 0.2));
     }
 
     // ---------- MapPoint ----------
@@ -1492,6 +2210,52 @@ mod tests {
         assert_eq!(p.get_name(), "Jita");
     }
 
+    #[test]
+    fn map_transform_roundtrip() {
+        let transform = MapTransform {
+            zoom: 2.0,
+            origin: RawPoint::new(-100.0, 50.0),
+        };
+        assert_eq!(
+            transform.map_to_screen([10.0, 10.0]),
+            Pos2::new(120.0, -30.0)
+        );
+        assert_eq!(
+            transform.screen_to_map(Pos2::new(120.0, -30.0)),
+            [10.0, 10.0]
+        );
+        assert_eq!(transform.scale(5.0), 10.0);
+    }
+
+    #[test]
+    fn map_point_data_is_typed_and_shared() {
+        let mut p = MapPoint::new(1, [0.0, 0.0]);
+        assert!(p.data.is_empty());
+        assert!(p.data::<u32>().is_none());
+
+        p.set_data(7u32);
+        assert_eq!(p.data::<u32>(), Some(&7));
+        // un tipo distinto no se puede leer
+        assert!(p.data::<i64>().is_none());
+
+        // clonar comparte el mismo valor
+        let clone = p.clone();
+        assert_eq!(clone, p);
+        p.set_data(7u32);
+        assert_ne!(clone, p);
+        assert_eq!(format!("{:?}", NodeData::default()), "NodeData(None)");
+
+        // los nodos se pueden construir en otro hilo
+        let built = std::thread::spawn(|| {
+            let mut point = MapPoint::new(2, [1.0, 1.0]);
+            point.set_data(String::from("worker"));
+            point
+        })
+        .join()
+        .unwrap();
+        assert_eq!(built.data::<String>().unwrap(), "worker");
+    }
+
     #[test]
     fn map_point_from_occupied_entry() {
         let mut map: HashMap<usize, MapPoint> = HashMap::new();
@@ -1549,6 +2313,15 @@ mod tests {
         assert_eq!(s.line_visible_zoom, 0.2);
         assert_eq!(s.label_visible_zoom, 0.58);
         assert_eq!(s.node_text_visibility, VisibilitySetting::Always);
+        assert_eq!(s.pick_radius, 8.0);
+        assert_eq!(s.selection_mode, SelectionMode::Multiple);
+        assert_eq!(s.drag_behavior, DragBehavior::Pan);
+        assert_eq!(s.selection_shape, SelectionShape::Rectangle);
+        assert_eq!(s.scroll_action, ScrollAction::Zoom);
+        assert!(s.long_press_menu);
+        assert_eq!(s.search_box, SearchBox::Hidden);
+        assert!(!s.declutter_labels);
+        assert_eq!(s.cluster_radius, 0.0);
         // light + dark themes
         assert_eq!(s.styles.len(), 2);
         // light theme
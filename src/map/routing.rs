@@ -0,0 +1,540 @@
+//! Shortest-path routing over the connections between nodes.
+//!
+//! The nodes loaded into a [`Map`](crate::map::Map) and their
+//! [`MapPoint::connections`] form a graph: two nodes are adjacent when both
+//! list the same connection id. [`RoutingGraph`] captures that graph once so
+//! several routes can be computed without rescanning the nodes; for a one-off
+//! query use [`Map::shortest_path`](crate::map::Map::shortest_path).
+
+use crate::map::objects::{MapPoint, MapSegment, RawLine, RawPoint};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// What a route minimizes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RouteMetric {
+    /// The number of jumps between nodes.
+    Hops,
+    /// The total length of the traversed lines, in map units.
+    Length,
+}
+
+/// A connection between two adjacent nodes, as seen while routing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RouteEdge {
+    /// Node the route leaves from.
+    pub from: usize,
+    /// Node the route arrives at.
+    pub to: usize,
+    /// Id of the connection, as listed in [`MapPoint::connections`].
+    pub segment: (usize, usize),
+    /// Length of the line, or the distance between both nodes when no line
+    /// with that id was loaded.
+    pub length: f32,
+}
+
+/// A path found by [`RoutingGraph::shortest_path`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    /// Visited nodes, from origin to destination (both included).
+    pub nodes: Vec<usize>,
+    /// Traversed connection ids; `segments[i]` joins `nodes[i]` and
+    /// `nodes[i + 1]`.
+    pub segments: Vec<(usize, usize)>,
+    /// Total cost under the metric used to compute the route.
+    pub cost: f32,
+}
+
+/// Adjacency graph built from node connections.
+///
+/// # Examples
+///
+/// ```
+/// use egui_map::map::Map;
+/// use egui_map::map::objects::MapPoint;
+/// use egui_map::map::routing::RouteMetric;
+///
+/// let mut points = vec![
+///     MapPoint::new(1, [0.0, 0.0]),
+///     MapPoint::new(2, [10.0, 0.0]),
+///     MapPoint::new(3, [20.0, 0.0]),
+/// ];
+/// points[0].connections.push((1, 2));
+/// points[1].connections.extend([(1, 2), (2, 3)]);
+/// points[2].connections.push((2, 3));
+///
+/// let mut map = Map::new();
+/// map.add_points(points);
+///
+/// let graph = map.routing_graph();
+/// let route = graph.shortest_path(1, 3, RouteMetric::Hops).unwrap();
+/// assert_eq!(route.nodes, vec![1, 2, 3]);
+/// assert_eq!(route.segments, vec![(1, 2), (2, 3)]);
+/// assert_eq!(route.cost, 2.0);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct RoutingGraph {
+    adjacency: HashMap<usize, Vec<RouteEdge>>,
+}
+
+impl RoutingGraph {
+    /// Builds the graph from a node set and the lines loaded for it.
+    ///
+    /// A connection id joins the two nodes listing it. If only one node lists
+    /// it, the id is read as the `(node, node)` pair it conventionally is, and
+    /// the connection is kept when the other node of the pair exists. Ids
+    /// listed by more than two nodes are ambiguous and ignored. The line
+    /// geometry is only used for the [`RouteEdge::length`].
+    pub fn new<'a>(
+        points: &HashMap<usize, MapPoint>,
+        segments: impl IntoIterator<Item = &'a MapSegment>,
+    ) -> Self {
+        let _span = tracing::info_span!("routing_graph_new").entered();
+        let lengths: HashMap<(usize, usize), f32> = segments
+            .into_iter()
+            .map(|segment| {
+                let line = RawLine::new(segment.point1.into(), segment.point2.into());
+                (segment.id, line.distance())
+            })
+            .collect();
+
+        let mut adjacency: HashMap<usize, Vec<RouteEdge>> =
+            points.keys().map(|id| (*id, Vec::new())).collect();
+        for (segment, (a, b)) in connection_endpoints(points) {
+            let length = lengths.get(&segment).copied().unwrap_or_else(|| {
+                RawLine::new(RawPoint::from(points[&a].coords), points[&b].coords.into()).distance()
+            });
+            for (from, to) in [(a, b), (b, a)] {
+                if let Some(edges) = adjacency.get_mut(&from) {
+                    edges.push(RouteEdge {
+                        from,
+                        to,
+                        segment,
+                        length,
+                    });
+                }
+            }
+        }
+        Self { adjacency }
+    }
+
+    /// Returns the connections leaving `node_id`.
+    pub fn edges(&self, node_id: usize) -> &[RouteEdge] {
+        self.adjacency
+            .get(&node_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Returns `true` if the graph contains the node.
+    pub fn contains(&self, node_id: usize) -> bool {
+        self.adjacency.contains_key(&node_id)
+    }
+
+    /// Returns the nodes reachable from `origin` within `max_hops` jumps,
+    /// grouped by hop count: `result[n]` holds the nodes exactly `n` jumps
+    /// away, so `result[0]` is `[origin]`.
+    ///
+    /// Stops early when no more nodes can be reached, so the result may hold
+    /// fewer than `max_hops + 1` groups. Returns an empty list if `origin` is
+    /// unknown. Nodes within a group are in no particular order.
+    pub fn neighborhood(&self, origin: usize, max_hops: usize) -> Vec<Vec<usize>> {
+        let _span = tracing::info_span!("neighborhood").entered();
+        if !self.contains(origin) {
+            return Vec::new();
+        }
+        let mut seen = HashSet::from([origin]);
+        let mut rings = vec![vec![origin]];
+        while rings.len() <= max_hops {
+            let next: Vec<usize> = rings
+                .last()
+                .into_iter()
+                .flatten()
+                .flat_map(|node| self.edges(*node))
+                .filter_map(|edge| seen.insert(edge.to).then_some(edge.to))
+                .collect();
+            if next.is_empty() {
+                break;
+            }
+            rings.push(next);
+        }
+        rings
+    }
+
+    /// Finds the route between two nodes minimizing `metric`.
+    ///
+    /// Returns `None` if either node is unknown or the destination can't be
+    /// reached.
+    pub fn shortest_path(&self, from: usize, to: usize, metric: RouteMetric) -> Option<Route> {
+        match metric {
+            RouteMetric::Hops => self.shortest_path_by(from, to, |_| Some(1.0)),
+            RouteMetric::Length => self.shortest_path_by(from, to, |edge| Some(edge.length)),
+        }
+    }
+
+    /// Finds the route between two nodes minimizing a custom cost.
+    ///
+    /// `cost` is called for every connection explored and returns its cost,
+    /// or `None` to forbid it (e.g. to avoid dangerous systems). Costs must
+    /// not be negative; negative values are treated as zero.
+    pub fn shortest_path_by(
+        &self,
+        from: usize,
+        to: usize,
+        cost: impl Fn(&RouteEdge) -> Option<f32>,
+    ) -> Option<Route> {
+        let _span = tracing::info_span!("shortest_path").entered();
+        if !self.contains(from) || !self.contains(to) {
+            return None;
+        }
+        let mut best: HashMap<usize, f32> = HashMap::from([(from, 0.0)]);
+        let mut previous: HashMap<usize, &RouteEdge> = HashMap::new();
+        let mut queue = BinaryHeap::from([Candidate {
+            cost: 0.0,
+            node: from,
+        }]);
+
+        while let Some(Candidate { cost: spent, node }) = queue.pop() {
+            if node == to {
+                return Some(self.build_route(from, to, spent, &previous));
+            }
+            if best.get(&node).is_some_and(|known| spent > *known) {
+                continue;
+            }
+            for edge in self.edges(node) {
+                let Some(step) = cost(edge) else {
+                    continue;
+                };
+                let total = spent + step.max(0.0);
+                if best.get(&edge.to).is_none_or(|known| total < *known) {
+                    best.insert(edge.to, total);
+                    previous.insert(edge.to, edge);
+                    queue.push(Candidate {
+                        cost: total,
+                        node: edge.to,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    fn build_route(
+        &self,
+        from: usize,
+        to: usize,
+        cost: f32,
+        previous: &HashMap<usize, &RouteEdge>,
+    ) -> Route {
+        let mut nodes = vec![to];
+        let mut segments = Vec::new();
+        let mut current = to;
+        while current != from {
+            let edge = previous[&current];
+            segments.push(edge.segment);
+            nodes.push(edge.from);
+            current = edge.from;
+        }
+        nodes.reverse();
+        segments.reverse();
+        Route {
+            nodes,
+            segments,
+            cost,
+        }
+    }
+}
+
+/// Pairs every connection id with the two nodes it joins, following the
+/// rules described in [`RoutingGraph::new`].
+pub(crate) fn connection_endpoints(
+    points: &HashMap<usize, MapPoint>,
+) -> HashMap<(usize, usize), (usize, usize)> {
+    ConnectionIndex::new(points).pairs
+}
+
+/// The pairing done by [`connection_endpoints`], kept up to date as single
+/// nodes change.
+///
+/// Besides the pairs it remembers which nodes list each connection and
+/// which connections each node may take part in, so re-pairing after an
+/// edit only looks at the connections around the edited node.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ConnectionIndex {
+    /// The two nodes joined by each paired connection.
+    pub pairs: HashMap<(usize, usize), (usize, usize)>,
+    /// Nodes listing each connection, in no particular order.
+    listed_by: HashMap<(usize, usize), Vec<usize>>,
+    /// Connections a node lists, or whose id names the node and so may be
+    /// paired with it one-sidedly.
+    touching: HashMap<usize, HashSet<(usize, usize)>>,
+}
+
+impl ConnectionIndex {
+    /// Indexes and pairs the connections of every node.
+    pub fn new(points: &HashMap<usize, MapPoint>) -> Self {
+        let mut index = Self::default();
+        for point in points.values() {
+            index.list(point.id, &point.connections);
+        }
+        let connections: Vec<(usize, usize)> = index.listed_by.keys().copied().collect();
+        for connection in connections {
+            index.pair(points, connection);
+        }
+        index
+    }
+
+    /// Records that `node` lists `connections`. Call [`Self::pair`] on them
+    /// afterwards.
+    pub fn list(&mut self, node: usize, connections: &[(usize, usize)]) {
+        for connection in connections {
+            let nodes = self.listed_by.entry(*connection).or_default();
+            if !nodes.contains(&node) {
+                nodes.push(node);
+            }
+            for id in [node, connection.0, connection.1] {
+                self.touching.entry(id).or_default().insert(*connection);
+            }
+        }
+    }
+
+    /// Records that `node` no longer lists `connections`. Call
+    /// [`Self::pair`] on them afterwards.
+    pub fn unlist(&mut self, node: usize, connections: &[(usize, usize)]) {
+        for connection in connections {
+            let Some(nodes) = self.listed_by.get_mut(connection) else {
+                continue;
+            };
+            nodes.retain(|id| *id != node);
+            let forget: &[usize] = if nodes.is_empty() {
+                self.listed_by.remove(connection);
+                &[node, connection.0, connection.1]
+            } else if node != connection.0 && node != connection.1 {
+                &[node]
+            } else {
+                &[]
+            };
+            for id in forget {
+                if let Some(touching) = self.touching.get_mut(id) {
+                    touching.remove(connection);
+                    if touching.is_empty() {
+                        self.touching.remove(id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the connections whose pairing may change when `node` does.
+    pub fn touching(&self, node: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.touching.get(&node).into_iter().flatten().copied()
+    }
+
+    /// Pairs `connection` again with the nodes currently listing it and
+    /// returns the new pair, if any.
+    pub fn pair(
+        &mut self,
+        points: &HashMap<usize, MapPoint>,
+        connection: (usize, usize),
+    ) -> Option<(usize, usize)> {
+        let listed = self
+            .listed_by
+            .get(&connection)
+            .map_or(&[][..], Vec::as_slice);
+        let pair = match *listed {
+            [a, b] => Some((a, b)),
+            [a] => one_sided_pair(points, connection, a),
+            _ => None,
+        };
+        match pair {
+            Some(pair) => self.pairs.insert(connection, pair),
+            None => self.pairs.remove(&connection),
+        };
+        pair
+    }
+}
+
+/// Reads `connection` as a node id pair including `node`, if the other node
+/// of the pair exists.
+pub(crate) fn one_sided_pair(
+    points: &HashMap<usize, MapPoint>,
+    connection: (usize, usize),
+    node: usize,
+) -> Option<(usize, usize)> {
+    let other = match connection {
+        (a, b) if a == node => b,
+        (a, b) if b == node => a,
+        _ => return None,
+    };
+    (other != node && points.contains_key(&other)).then_some((node, other))
+}
+
+/// Priority queue entry; ordered so the cheapest candidate pops first.
+struct Candidate {
+    cost: f32,
+    node: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.node.cmp(&self.node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cuadrado 1-2-3-4 con una diagonal larga 1-3:
+    ///
+    /// ```text
+    /// 1 (0,0) ---- 2 (10,0)
+    /// |        \       |
+    /// 4 (0,10) ---- 3 (10,10)
+    /// ```
+    fn square() -> HashMap<usize, MapPoint> {
+        let coords = [[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0]];
+        let mut points: HashMap<usize, MapPoint> = (1..=4)
+            .map(|id| (id, MapPoint::new(id, coords[id - 1])))
+            .collect();
+        for (a, b) in [(1, 2), (2, 3), (3, 4), (4, 1), (1, 3)] {
+            points.get_mut(&a).unwrap().connections.push((a, b));
+            points.get_mut(&b).unwrap().connections.push((a, b));
+        }
+        points
+    }
+
+    #[test]
+    fn hops_prefer_fewest_jumps() {
+        let graph = RoutingGraph::new(&square(), []);
+        let route = graph.shortest_path(1, 3, RouteMetric::Hops).unwrap();
+        assert_eq!(route.nodes, vec![1, 3]);
+        assert_eq!(route.segments, vec![(1, 3)]);
+        assert_eq!(route.cost, 1.0);
+    }
+
+    #[test]
+    fn length_uses_segment_geometry() {
+        // la diagonal 1-3 se dibuja con un rodeo y pasa a ser la más larga
+        let detour = MapSegment::new((1, 3), [0.0, 0.0], [40.0, 40.0]);
+        let graph = RoutingGraph::new(&square(), [&detour]);
+        let route = graph.shortest_path(1, 3, RouteMetric::Length).unwrap();
+        assert_eq!(route.nodes.len(), 3);
+        assert_eq!(route.cost, 20.0);
+
+        // sin geometría se usa la distancia entre nodos
+        let graph = RoutingGraph::new(&square(), []);
+        let route = graph.shortest_path(1, 3, RouteMetric::Length).unwrap();
+        assert_eq!(route.nodes, vec![1, 3]);
+        assert!((route.cost - 200.0_f32.sqrt()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn cost_callback_can_forbid_nodes() {
+        let graph = RoutingGraph::new(&square(), []);
+        let route = graph
+            .shortest_path_by(1, 3, |edge| {
+                (edge.to != 2 && edge.segment != (1, 3)).then_some(1.0)
+            })
+            .unwrap();
+        assert_eq!(route.nodes, vec![1, 4, 3]);
+        assert_eq!(route.segments, vec![(4, 1), (3, 4)]);
+
+        assert!(graph.shortest_path_by(1, 3, |_| None).is_none());
+    }
+
+    #[test]
+    fn unknown_and_unreachable_nodes() {
+        let mut points = square();
+        points.insert(5, MapPoint::new(5, [50.0, 50.0]));
+        let graph = RoutingGraph::new(&points, []);
+        assert!(graph.shortest_path(1, 5, RouteMetric::Hops).is_none());
+        assert!(graph.shortest_path(1, 99, RouteMetric::Hops).is_none());
+
+        let route = graph.shortest_path(5, 5, RouteMetric::Hops).unwrap();
+        assert_eq!(route.nodes, vec![5]);
+        assert!(route.segments.is_empty());
+        assert_eq!(route.cost, 0.0);
+    }
+
+    #[test]
+    fn neighborhood_groups_nodes_by_hops() {
+        let mut points = square();
+        // una cola 3-5-6 y un nodo aislado 7
+        for (id, coords) in [(5, [20.0, 20.0]), (6, [30.0, 30.0]), (7, [0.0, 50.0])] {
+            points.insert(id, MapPoint::new(id, coords));
+        }
+        for (a, b) in [(3, 5), (5, 6)] {
+            points.get_mut(&a).unwrap().connections.push((a, b));
+            points.get_mut(&b).unwrap().connections.push((a, b));
+        }
+        let graph = RoutingGraph::new(&points, []);
+
+        let mut rings = graph.neighborhood(1, 10);
+        rings.iter_mut().for_each(|ring| ring.sort());
+        assert_eq!(rings, vec![vec![1], vec![2, 3, 4], vec![5], vec![6]]);
+
+        assert_eq!(graph.neighborhood(1, 1).len(), 2);
+        assert_eq!(graph.neighborhood(1, 0), vec![vec![1]]);
+        assert_eq!(graph.neighborhood(7, 3), vec![vec![7]]);
+        assert!(graph.neighborhood(99, 3).is_empty());
+    }
+
+    #[test]
+    fn one_sided_connections_fall_back_to_the_id_pair() {
+        let mut points = HashMap::new();
+        let mut a = MapPoint::new(1, [0.0, 0.0]);
+        a.connections.push((1, 2));
+        points.insert(1, a);
+        points.insert(2, MapPoint::new(2, [3.0, 4.0]));
+        let graph = RoutingGraph::new(&points, []);
+        assert_eq!(graph.edges(2).len(), 1);
+        assert_eq!(graph.edges(2)[0].length, 5.0);
+
+        // un id que no nombra al nodo se ignora
+        points.get_mut(&1).unwrap().connections = vec![(7, 8)];
+        let graph = RoutingGraph::new(&points, []);
+        assert!(graph.edges(1).is_empty());
+    }
+
+    #[test]
+    fn connection_index_repairs_only_around_a_node() {
+        let mut points = square();
+        let mut index = ConnectionIndex::new(&points);
+        assert_eq!(index.pairs, connection_endpoints(&points));
+        let mut around_two: Vec<_> = index.touching(2).collect();
+        around_two.sort_unstable();
+        assert_eq!(around_two, vec![(1, 2), (2, 3)]);
+
+        // el nodo 2 deja de listar (1,2): queda emparejada por un solo lado
+        let old = std::mem::take(&mut points.get_mut(&2).unwrap().connections);
+        index.unlist(2, &old);
+        index.list(2, &[(2, 3)]);
+        points.get_mut(&2).unwrap().connections = vec![(2, 3)];
+        assert_eq!(index.pair(&points, (1, 2)), Some((1, 2)));
+
+        points.remove(&2);
+        index.unlist(2, &[(2, 3)]);
+        // sin el nodo 2 ninguna de sus conexiones se empareja
+        assert_eq!(index.pair(&points, (1, 2)), None);
+        assert_eq!(index.pair(&points, (2, 3)), None);
+        assert!(!index.pairs.contains_key(&(2, 3)));
+    }
+}
@@ -0,0 +1,275 @@
+//! Case-insensitive search over node names.
+//!
+//! [`Map`](crate::map::Map) keeps a [`SearchIndex`] of the
+//! [`MapPoint::name`] of every loaded node, updated as nodes are added,
+//! replaced or removed. Query it with
+//! [`Map::search`](crate::map::Map::search), or enable the built-in search box
+//! with [`MapSettings::search_box`](crate::map::objects::MapSettings::search_box).
+
+use crate::map::objects::MapPoint;
+use std::collections::{BTreeSet, HashMap};
+
+/// How well a node name matches a query, best first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchQuality {
+    /// The name is the query.
+    Exact,
+    /// The name starts with the query.
+    Prefix,
+    /// A word inside the name starts with the query.
+    WordPrefix,
+    /// The query appears somewhere inside the name.
+    Substring,
+    /// The characters of the query appear in the name in the same order,
+    /// e.g. `jta` in `Jita`.
+    Subsequence,
+    /// The name starts with the query misspelled by this many characters.
+    Typo(usize),
+}
+
+/// A node found by [`SearchIndex::search`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchMatch {
+    /// Id of the node.
+    pub id: usize,
+    /// Name of the node, as it was loaded.
+    pub name: String,
+    /// How well the name matches the query.
+    pub quality: MatchQuality,
+}
+
+/// Index of node names for prefix and fuzzy lookups.
+///
+/// # Examples
+///
+/// ```
+/// use egui_map::map::search::{MatchQuality, SearchIndex};
+///
+/// let mut index = SearchIndex::default();
+/// index.insert(1, "Jita");
+/// index.insert(2, "New Caldari");
+/// index.insert(3, "Perimeter");
+///
+/// let found = index.search("jta", 5);
+/// assert_eq!(found[0].id, 1);
+/// assert_eq!(found[0].quality, MatchQuality::Subsequence);
+/// assert_eq!(index.search("cald", 5)[0].quality, MatchQuality::WordPrefix);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SearchIndex {
+    /// Lowercase names, sorted so prefix queries are a range scan.
+    sorted: BTreeSet<(String, usize)>,
+    /// Lowercase and original name of every indexed node.
+    names: HashMap<usize, (String, String)>,
+}
+
+impl SearchIndex {
+    /// Builds an index over the names of `points`; unnamed nodes are skipped.
+    pub fn new<'a>(points: impl IntoIterator<Item = &'a MapPoint>) -> Self {
+        let mut index = Self::default();
+        for point in points {
+            if let Some(name) = &point.name {
+                index.insert(point.id, name);
+            }
+        }
+        index
+    }
+
+    /// Indexes `name` under the node id, replacing its previous name.
+    pub fn insert(&mut self, id: usize, name: &str) {
+        self.remove(id);
+        let lowercase = name.to_lowercase();
+        self.sorted.insert((lowercase.clone(), id));
+        self.names.insert(id, (lowercase, name.to_string()));
+    }
+
+    /// Drops the node id from the index.
+    pub fn remove(&mut self, id: usize) {
+        if let Some((lowercase, _)) = self.names.remove(&id) {
+            self.sorted.remove(&(lowercase, id));
+        }
+    }
+
+    /// Returns the number of indexed names.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Returns `true` if no name is indexed.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Returns up to `limit` nodes whose name matches `query`, best first.
+    ///
+    /// Matching ignores case and surrounding whitespace. Results are ranked
+    /// by [`MatchQuality`], then shorter names first. Misspellings are only
+    /// tolerated for queries of three characters or more, one per four
+    /// characters typed.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchMatch> {
+        let _span = tracing::info_span!("search_index_search").entered();
+        let query = query.trim().to_lowercase();
+        if query.is_empty() || limit == 0 {
+            return Vec::new();
+        }
+        let mut found: Vec<(MatchQuality, usize)> = self
+            .sorted
+            .range((query.clone(), 0)..)
+            .take_while(|(name, _)| name.starts_with(&query))
+            .map(|(name, id)| {
+                let quality = if *name == query {
+                    MatchQuality::Exact
+                } else {
+                    MatchQuality::Prefix
+                };
+                (quality, *id)
+            })
+            .collect();
+        // prefix matches always rank first, so only scan when they fall short
+        if found.len() < limit {
+            let query: Vec<char> = query.chars().collect();
+            let max_typos = if query.len() >= 3 { query.len() / 4 } else { 0 };
+            for (id, (name, _)) in &self.names {
+                if let Some(quality) = fuzzy_quality(&query, name, max_typos) {
+                    found.push((quality, *id));
+                }
+            }
+        }
+        let mut found: Vec<SearchMatch> = found
+            .into_iter()
+            .map(|(quality, id)| SearchMatch {
+                id,
+                name: self.names[&id].1.clone(),
+                quality,
+            })
+            .collect();
+        found.sort_by(|a, b| {
+            a.quality
+                .cmp(&b.quality)
+                .then(a.name.len().cmp(&b.name.len()))
+                .then_with(|| a.name.cmp(&b.name))
+                .then(a.id.cmp(&b.id))
+        });
+        found.truncate(limit);
+        found
+    }
+}
+
+/// Grades a lowercase name that does not start with the query.
+fn fuzzy_quality(query: &[char], name: &str, max_typos: usize) -> Option<MatchQuality> {
+    let chars: Vec<char> = name.chars().collect();
+    if chars.starts_with(query) {
+        // already found by the prefix scan
+        return None;
+    }
+    let query_str: String = query.iter().collect();
+    if name
+        .split(|c: char| !c.is_alphanumeric())
+        .skip(1)
+        .any(|word| word.starts_with(&query_str))
+    {
+        return Some(MatchQuality::WordPrefix);
+    }
+    if name.contains(&query_str) {
+        return Some(MatchQuality::Substring);
+    }
+    let mut rest = chars.iter();
+    if query.iter().all(|c| rest.any(|n| n == c)) {
+        return Some(MatchQuality::Subsequence);
+    }
+    if max_typos == 0 {
+        return None;
+    }
+    // compare against name prefixes one character shorter or longer too, so
+    // a missing or extra letter counts as a single typo
+    let typos = (query.len().saturating_sub(1)..=query.len() + 1)
+        .filter(|len| *len <= chars.len())
+        .map(|len| edit_distance(query, &chars[..len]))
+        .min()?;
+    (typos <= max_typos).then_some(MatchQuality::Typo(typos))
+}
+
+/// Levenshtein distance between two character sequences.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> SearchIndex {
+        let mut index = SearchIndex::default();
+        for (id, name) in [
+            (1, "Jita"),
+            (2, "Jita Trade Hub"),
+            (3, "New Caldari"),
+            (4, "Perimeter"),
+            (5, "Amarr"),
+        ] {
+            index.insert(id, name);
+        }
+        index
+    }
+
+    fn ids(found: &[SearchMatch]) -> Vec<usize> {
+        found.iter().map(|found| found.id).collect()
+    }
+
+    #[test]
+    fn prefix_matches_rank_first_and_ignore_case() {
+        let found = index().search("  JITA ", 10);
+        assert_eq!(ids(&found), vec![1, 2]);
+        assert_eq!(found[0].quality, MatchQuality::Exact);
+        assert_eq!(found[1].quality, MatchQuality::Prefix);
+        assert_eq!(found[1].name, "Jita Trade Hub");
+        // el límite se respeta
+        assert_eq!(index().search("j", 1).len(), 1);
+    }
+
+    #[test]
+    fn fuzzy_matches_are_graded() {
+        let index = index();
+        assert_eq!(
+            index.search("trade", 5)[0].quality,
+            MatchQuality::WordPrefix
+        );
+        assert_eq!(index.search("mete", 5)[0].quality, MatchQuality::Substring);
+        assert_eq!(index.search("prm", 5)[0].quality, MatchQuality::Subsequence);
+        let typo = index.search("amxr", 5);
+        assert_eq!(ids(&typo), vec![5]);
+        assert_eq!(typo[0].quality, MatchQuality::Typo(1));
+        // las consultas cortas no toleran errores
+        assert!(index.search("zz", 5).is_empty());
+        assert!(index.search("", 5).is_empty());
+    }
+
+    #[test]
+    fn insert_replaces_and_remove_drops_names() {
+        let mut index = index();
+        index.insert(1, "Dodixie");
+        assert_eq!(ids(&index.search("jita", 5)), vec![2]);
+        assert_eq!(ids(&index.search("dodi", 5)), vec![1]);
+        index.remove(1);
+        assert!(index.search("dodi", 5).is_empty());
+        assert_eq!(index.len(), 4);
+    }
+
+    #[test]
+    fn edit_distance_counts_changes() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        assert_eq!(edit_distance(&chars("kitten"), &chars("sitting")), 3);
+        assert_eq!(edit_distance(&chars(""), &chars("abc")), 3);
+        assert_eq!(edit_distance(&chars("same"), &chars("same")), 0);
+    }
+}
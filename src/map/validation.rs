@@ -0,0 +1,313 @@
+//! Consistency checks for the nodes and lines loaded into a
+//! [`Map`](crate::map::Map).
+//!
+//! The widget never rejects inconsistent data on its own: a connection
+//! without a line is simply not drawn, and a node with a NaN coordinate never
+//! makes it into the spatial index. [`Map::validate`](crate::map::Map::validate)
+//! reports those problems instead, and
+//! [`Map::try_add_points`](crate::map::Map::try_add_points) /
+//! [`Map::try_add_lines`](crate::map::Map::try_add_lines) refuse to load data
+//! that has them.
+
+use crate::map::objects::{MapPoint, MapSegment};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Maximum distance, in map units, between a line end and the node it joins.
+const ENDPOINT_TOLERANCE: f32 = 1e-3;
+
+/// A single problem found in the map data.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationIssue {
+    /// A node lists a connection id with no matching [`MapSegment`].
+    DanglingConnection {
+        /// Node listing the connection.
+        node: usize,
+        /// Connection id without a line.
+        connection: (usize, usize),
+    },
+    /// A line whose id no node lists in its connections.
+    OrphanSegment {
+        /// Id of the line.
+        segment: (usize, usize),
+    },
+    /// A line that does not start or end at a node listing it.
+    MisalignedSegment {
+        /// Id of the line.
+        segment: (usize, usize),
+        /// Node listing the line whose coordinates match neither end.
+        node: usize,
+    },
+    /// Several nodes were loaded with the same id; only the last one was
+    /// kept.
+    DuplicateNodeId {
+        /// The repeated id.
+        id: usize,
+    },
+    /// A node was loaded under a map key different from its own id.
+    MismatchedNodeKey {
+        /// Key of the node in the loaded map.
+        key: usize,
+        /// Id of the node.
+        id: usize,
+    },
+    /// Several lines were loaded with the same id; only the last one was
+    /// kept.
+    DuplicateSegmentId {
+        /// The repeated id.
+        id: (usize, usize),
+    },
+    /// A line was loaded under a map key different from its own id.
+    MismatchedSegmentKey {
+        /// Key of the line in the loaded map.
+        key: (usize, usize),
+        /// Id of the line.
+        id: (usize, usize),
+    },
+    /// A node with a NaN or infinite coordinate.
+    NonFiniteNode {
+        /// Id of the node.
+        id: usize,
+    },
+    /// A line with a NaN or infinite coordinate.
+    NonFiniteSegment {
+        /// Id of the line.
+        id: (usize, usize),
+    },
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationIssue::DanglingConnection { node, connection } => {
+                write!(
+                    f,
+                    "node {node} lists connection {connection:?}, which has no line"
+                )
+            }
+            ValidationIssue::OrphanSegment { segment } => {
+                write!(f, "line {segment:?} is not listed by any node")
+            }
+            ValidationIssue::MisalignedSegment { segment, node } => {
+                write!(f, "line {segment:?} does not start or end at node {node}")
+            }
+            ValidationIssue::DuplicateNodeId { id } => write!(f, "node id {id} is repeated"),
+            ValidationIssue::MismatchedNodeKey { key, id } => {
+                write!(f, "node {id} was loaded under key {key}")
+            }
+            ValidationIssue::DuplicateSegmentId { id } => {
+                write!(f, "line id {id:?} is repeated")
+            }
+            ValidationIssue::MismatchedSegmentKey { key, id } => {
+                write!(f, "line {id:?} was loaded under key {key:?}")
+            }
+            ValidationIssue::NonFiniteNode { id } => {
+                write!(f, "node {id} has a non-finite coordinate")
+            }
+            ValidationIssue::NonFiniteSegment { id } => {
+                write!(f, "line {id:?} has a non-finite coordinate")
+            }
+        }
+    }
+}
+
+/// The problems found by [`Map::validate`](crate::map::Map::validate).
+///
+/// Also returned as the error of the strict loaders. An empty report means
+/// the data is consistent.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ValidationReport {
+    /// Every problem found, in no particular order.
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// Returns `true` if no problem was found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Turns the report into a `Result`, failing if any problem was found.
+    pub fn into_result(self) -> Result<(), ValidationReport> {
+        if self.is_ok() { Ok(()) } else { Err(self) }
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} map data issue(s)", self.issues.len())?;
+        for issue in &self.issues {
+            write!(f, "\n- {issue}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationReport {}
+
+/// Checks a list of nodes before loading it: repeated ids and non-finite
+/// coordinates.
+pub(crate) fn check_points<'a>(
+    points: impl IntoIterator<Item = &'a MapPoint>,
+) -> Vec<ValidationIssue> {
+    let mut seen = HashSet::new();
+    let mut issues = Vec::new();
+    for point in points {
+        if !seen.insert(point.id) {
+            issues.push(ValidationIssue::DuplicateNodeId { id: point.id });
+        }
+        if !point.coords.iter().all(|value| value.is_finite()) {
+            issues.push(ValidationIssue::NonFiniteNode { id: point.id });
+        }
+    }
+    issues
+}
+
+/// Checks a list of lines before loading it for repeated ids; the rest is
+/// covered by [`check_graph`].
+pub(crate) fn check_segment_ids<'a>(
+    segments: impl IntoIterator<Item = &'a MapSegment>,
+) -> Vec<ValidationIssue> {
+    let mut seen = HashSet::new();
+    segments
+        .into_iter()
+        .filter(|segment| !seen.insert(segment.id))
+        .map(|segment| ValidationIssue::DuplicateSegmentId { id: segment.id })
+        .collect()
+}
+
+/// Cross-checks the loaded nodes against the loaded lines.
+pub(crate) fn check_graph<'a>(
+    points: &HashMap<usize, MapPoint>,
+    segments: impl IntoIterator<Item = &'a MapSegment>,
+) -> Vec<ValidationIssue> {
+    let segments: HashMap<(usize, usize), &MapSegment> = segments
+        .into_iter()
+        .map(|segment| (segment.id, segment))
+        .collect();
+    let mut listed = HashSet::new();
+    let mut issues = Vec::new();
+
+    for point in points.values() {
+        if !point.coords.iter().all(|value| value.is_finite()) {
+            issues.push(ValidationIssue::NonFiniteNode { id: point.id });
+        }
+        for connection in &point.connections {
+            listed.insert(*connection);
+            match segments.get(connection) {
+                None => issues.push(ValidationIssue::DanglingConnection {
+                    node: point.id,
+                    connection: *connection,
+                }),
+                Some(segment) => {
+                    let touches = |end: [f32; 2]| {
+                        (end[0] - point.coords[0]).abs() <= ENDPOINT_TOLERANCE
+                            && (end[1] - point.coords[1]).abs() <= ENDPOINT_TOLERANCE
+                    };
+                    if !touches(segment.point1) && !touches(segment.point2) {
+                        issues.push(ValidationIssue::MisalignedSegment {
+                            segment: *connection,
+                            node: point.id,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    for (id, segment) in &segments {
+        if !listed.contains(id) {
+            issues.push(ValidationIssue::OrphanSegment { segment: *id });
+        }
+        if !segment
+            .point1
+            .iter()
+            .chain(&segment.point2)
+            .all(|value| value.is_finite())
+        {
+            issues.push(ValidationIssue::NonFiniteSegment { id: *id });
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connected(id: usize, coords: [f32; 2], connections: &[(usize, usize)]) -> MapPoint {
+        let mut point = MapPoint::new(id, coords);
+        point.connections = connections.to_vec();
+        point
+    }
+
+    #[test]
+    fn consistent_graph_has_no_issues() {
+        let points = HashMap::from([
+            (1, connected(1, [0.0, 0.0], &[(1, 2)])),
+            (2, connected(2, [10.0, 0.0], &[(1, 2)])),
+        ]);
+        let segment = MapSegment::new((1, 2), [10.0, 0.0], [0.0, 0.0]);
+        assert!(check_graph(&points, [&segment]).is_empty());
+    }
+
+    #[test]
+    fn graph_issues_are_reported() {
+        let points = HashMap::from([
+            (1, connected(1, [0.0, 0.0], &[(1, 2), (1, 3)])),
+            (2, connected(2, [10.0, 0.0], &[(1, 2)])),
+            (3, connected(3, [f32::NAN, 0.0], &[])),
+        ]);
+        // (1,2) no llega al nodo 2 y (5,6) no la lista nadie
+        let segments = [
+            MapSegment::new((1, 2), [0.0, 0.0], [9.0, 0.0]),
+            MapSegment::new((5, 6), [0.0, 0.0], [1.0, f32::INFINITY]),
+        ];
+        let issues = check_graph(&points, &segments);
+        assert_eq!(issues.len(), 5);
+        for expected in [
+            ValidationIssue::DanglingConnection {
+                node: 1,
+                connection: (1, 3),
+            },
+            ValidationIssue::MisalignedSegment {
+                segment: (1, 2),
+                node: 2,
+            },
+            ValidationIssue::NonFiniteNode { id: 3 },
+            ValidationIssue::OrphanSegment { segment: (5, 6) },
+            ValidationIssue::NonFiniteSegment { id: (5, 6) },
+        ] {
+            assert!(issues.contains(&expected), "falta {expected:?}");
+        }
+    }
+
+    #[test]
+    fn input_lists_report_duplicates() {
+        let points = [MapPoint::new(1, [0.0, 0.0]), MapPoint::new(1, [1.0, 1.0])];
+        assert_eq!(
+            check_points(&points),
+            vec![ValidationIssue::DuplicateNodeId { id: 1 }]
+        );
+        let segments = [
+            MapSegment::new((1, 2), [0.0, 0.0], [1.0, 1.0]),
+            MapSegment::new((1, 2), [0.0, 0.0], [1.0, 1.0]),
+        ];
+        assert_eq!(
+            check_segment_ids(&segments),
+            vec![ValidationIssue::DuplicateSegmentId { id: (1, 2) }]
+        );
+    }
+
+    #[test]
+    fn report_display_lists_issues() {
+        let report = ValidationReport {
+            issues: vec![ValidationIssue::DuplicateNodeId { id: 4 }],
+        };
+        assert_eq!(
+            report.to_string(),
+            "1 map data issue(s)\n- node id 4 is repeated"
+        );
+        assert!(report.clone().into_result().is_err());
+        assert!(ValidationReport::default().into_result().is_ok());
+    }
+}
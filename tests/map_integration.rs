@@ -7,11 +7,11 @@ use egui_map::map::objects::{
 use std::time::Instant;
 
 fn sample_points() -> Vec<MapPoint> {
-    let mut map = Vec::new();
-    map.push(MapPoint::new(1, [0.0, 0.0]));
-    map.push(MapPoint::new(2, [10.0, 10.0]));
-    map.push(MapPoint::new(3, [-10.0, -10.0]));
-    map
+    vec![
+        MapPoint::new(1, [0.0, 0.0]),
+        MapPoint::new(2, [10.0, 10.0]),
+        MapPoint::new(3, [-10.0, -10.0]),
+    ]
 }
 
 // ---------- flujo completo de Map (API pública) ----------
@@ -57,12 +57,12 @@ fn map_add_labels_and_lines() {
     map.add_points(sample_points());
 
     map.add_labels(vec![MapLabel {
-        text: "The Forge".to_string(),
-        center: egui::Pos2::new(3.0, 4.0),
+        min_zoom: 0.1,
+        max_zoom: Some(0.5),
+        ..MapLabel::at("The Forge", [3.0, 4.0])
     }]);
 
-    let mut lines = Vec::new();
-    lines.push(MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0]));
+    let lines = vec![MapSegment::new((1, 2), [0.0, 0.0], [10.0, 10.0])];
     map.add_lines(lines);
 }
 